@@ -0,0 +1,441 @@
+use crate::wfc::*;
+use crate::generation_fields::dungeon::*;
+use crate::generation_fields::dungeon_config::*;
+
+/// The first line of a saved probability profile
+const PROFILE_HEADER: &str = "dungeoroo-profile 1";
+
+/// How many CellCategory values there are
+pub const CELL_CATEGORY_COUNT: usize = 15;
+
+/// The groups of cell types that share a weight. Halls and rooms are grouped by how many connections they have.
+#[derive(Debug, Eq, Copy, Clone, PartialEq, Hash)]
+pub enum CellCategory {
+    None,
+    Hall(usize),
+    Room(usize),
+}
+
+impl CellCategory {
+    /// Every category, None first, then halls and rooms from 0 to 6 connections
+    pub fn all() -> Vec<CellCategory> {
+        let mut categories = vec![CellCategory::None];
+        categories.extend((0..=6).map(CellCategory::Hall));
+        categories.extend((0..=6).map(CellCategory::Room));
+
+        categories
+    }
+
+    /// Where the category is in CellCategory::all()
+    pub fn index(&self) -> usize {
+        match self {
+            CellCategory::None => 0,
+            CellCategory::Hall(connections) => 1 + connections,
+            CellCategory::Room(connections) => 8 + connections,
+        }
+    }
+
+    pub fn of(cell_type: &DungeonCellType) -> CellCategory {
+        match cell_type {
+            DungeonCellType::None => CellCategory::None,
+            DungeonCellType::Hall(connections) => CellCategory::Hall(connections.count()),
+            DungeonCellType::Room(connections) => CellCategory::Room(connections.count()),
+        }
+    }
+
+    /// How many cell types are in the category. There's one for each way of picking its connections.
+    pub fn type_count(&self) -> usize {
+        let choose = |connections: usize| (0..connections).fold(1, |count, i| count * (6 - i) / (i + 1));
+
+        match self {
+            CellCategory::None => 1,
+            CellCategory::Hall(connections) => choose(*connections),
+            CellCategory::Room(connections) => choose(*connections),
+        }
+    }
+
+    pub fn get_label(&self) -> String {
+        match self {
+            CellCategory::None => String::from("None"),
+            CellCategory::Hall(connections) => format!("Hall {}", connections),
+            CellCategory::Room(connections) => format!("Room {}", connections),
+        }
+    }
+}
+
+/// The relative weight of every cell type while filling, set per CellCategory
+#[derive(Debug, PartialEq, Clone)]
+pub struct CellProbabilityModel {
+    pub none: f64,
+    /// Indexed by connection count
+    pub halls: [f64; 7],
+    /// Indexed by connection count
+    pub rooms: [f64; 7],
+    /// Adjusts the weights by what's already next to a cell, if the model was fitted with neighbors
+    pub neighbor_weights: Option<NeighborWeights>,
+}
+
+impl Default for CellProbabilityModel {
+    /// Favors halls, with fewer connections being more likely, and never picks anything with more than 2 connections
+    fn default() -> Self {
+        let by_connections = |weight: f64| {
+            let mut weights = [0.0; 7];
+            for (connections, connections_weight) in weights.iter_mut().enumerate().take(3) {
+                *connections_weight = ((4.0 - connections as f64) / 6.0) * weight;
+            }
+
+            weights
+        };
+
+        CellProbabilityModel {
+            none: 0.1,
+            halls: by_connections(0.7),
+            rooms: by_connections(0.1),
+            neighbor_weights: None,
+        }
+    }
+}
+
+impl CellProbabilityModel {
+    /// Checks every weight is a non-negative number, and that something can be picked
+    pub fn validate(&self) -> Result<(), String> {
+        for category in CellCategory::all() {
+            let weight = self.get_weight(category);
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(format!("The {} weight can't be negative", category.get_label()));
+            }
+        }
+
+        if self.get_total() <= 0.0 {
+            return Err(String::from("At least one cell weight has to be above 0"));
+        }
+
+        if let Some(neighbor_weights) = &self.neighbor_weights {
+            if neighbor_weights.ratios.iter().any(|ratio| !ratio.is_finite() || *ratio < 0.0) {
+                return Err(String::from("Neighbor weights can't be negative"));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get_weight(&self, category: CellCategory) -> f64 {
+        match category {
+            CellCategory::None => self.none,
+            CellCategory::Hall(connections) => self.halls[connections],
+            CellCategory::Room(connections) => self.rooms[connections],
+        }
+    }
+
+    pub fn set_weight(&mut self, category: CellCategory, weight: f64) {
+        match category {
+            CellCategory::None => self.none = weight,
+            CellCategory::Hall(connections) => self.halls[connections] = weight,
+            CellCategory::Room(connections) => self.rooms[connections] = weight,
+        }
+    }
+
+    /// The relative likelihood of a cell type being picked while filling
+    pub fn cell_type_weight(&self, cell_type: &DungeonCellType) -> f64 {
+        self.get_weight(CellCategory::of(cell_type))
+    }
+
+    /// The relative likelihood of a cell type being picked for a cell next to the given settled neighbors.
+    /// Without neighbor weights, or without neighbors, this is just the cell type's weight.
+    pub fn cell_type_weight_near(&self, cell_type: &DungeonCellType, neighbors: &[CellCategory]) -> f64 {
+        let category = CellCategory::of(cell_type);
+        let weight = self.get_weight(category);
+
+        match &self.neighbor_weights {
+            None => weight,
+            Some(neighbor_weights) => neighbors.iter().fold(weight, |weight, neighbor| weight * neighbor_weights.get_ratio(*neighbor, category)),
+        }
+    }
+
+    /// The weight of every cell type added up
+    pub fn get_total(&self) -> f64 {
+        CellCategory::all().into_iter().map(|category| self.get_weight(category) * category.type_count() as f64).sum()
+    }
+
+    /// How often each category gets picked for a cell that could still be anything, as a fraction of all picks
+    pub fn distribution(&self) -> Vec<(CellCategory, f64)> {
+        let total = self.get_total();
+
+        CellCategory::all().into_iter().map(|category| {
+            let share = if total > 0.0 { self.get_weight(category) * category.type_count() as f64 / total } else { 0.0 };
+            (category, share)
+        }).collect()
+    }
+
+    /// Writes a list of weights as text, like 0.1,0.2,0
+    pub fn weights_to_text(weights: &[f64]) -> String {
+        weights.iter().map(|weight| weight.to_string()).collect::<Vec<String>>().join(",")
+    }
+
+    /// Reads 7 weights written by weights_to_text
+    pub fn weights_from_text(text: &str) -> Option<[f64; 7]> {
+        parse_weights(text)?.try_into().ok()
+    }
+
+    /// Writes the model as a profile, which holds just the weights so it can be loaded into any config
+    pub fn to_text(&self) -> String {
+        let config = DungeonGeneratorConfig { cell_weights: self.clone(), ..Default::default() };
+
+        let mut lines = vec![PROFILE_HEADER.to_string()];
+        for setting in DungeonSetting::ALL.into_iter().filter(|setting| setting.is_cell_weight()) {
+            lines.push(format!("{} {}", setting.get_keyword(), config.get_value(setting)));
+        }
+
+        lines.join("\n")
+    }
+
+    /// Reads a profile written by to_text. Weights that are left out keep their defaults.
+    pub fn from_text(text: &str) -> Result<CellProbabilityModel, String> {
+        let mut lines = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).enumerate();
+
+        match lines.next() {
+            Some((_, PROFILE_HEADER)) => {},
+            _ => return Err(format!("Profiles must start with \"{}\"", PROFILE_HEADER)),
+        }
+
+        let mut config = DungeonGeneratorConfig::default();
+        for (index, line) in lines {
+            let line_error = |message: &str| format!("Line {}: {}", index + 1, message);
+            let (keyword, value) = line.split_once(' ').ok_or_else(|| line_error("Expected a keyword and a value"))?;
+
+            match DungeonSetting::from_keyword(keyword) {
+                Some(setting) if setting.is_cell_weight() => config.set_value(setting, value).map_err(|message| line_error(&message))?,
+                _ => return Err(line_error(&format!("Unknown weight {}", keyword))),
+            }
+        }
+
+        config.cell_weights.validate()?;
+
+        Ok(config.cell_weights)
+    }
+}
+
+/// How much more or less likely each category is next to each other category, compared to anywhere
+#[derive(Debug, PartialEq, Clone)]
+pub struct NeighborWeights {
+    /// Indexed by the neighbor's category index times CELL_CATEGORY_COUNT, plus the category's index
+    ratios: Vec<f64>,
+}
+
+impl Default for NeighborWeights {
+    fn default() -> Self {
+        NeighborWeights::new()
+    }
+}
+
+impl NeighborWeights {
+    /// Creates weights that don't change anything
+    pub fn new() -> NeighborWeights {
+        NeighborWeights {
+            ratios: vec![1.0; CELL_CATEGORY_COUNT * CELL_CATEGORY_COUNT],
+        }
+    }
+
+    /// How many times more likely the category is next to the neighbor
+    pub fn get_ratio(&self, neighbor: CellCategory, category: CellCategory) -> f64 {
+        self.ratios[neighbor.index() * CELL_CATEGORY_COUNT + category.index()]
+    }
+
+    pub fn set_ratio(&mut self, neighbor: CellCategory, category: CellCategory, ratio: f64) {
+        self.ratios[neighbor.index() * CELL_CATEGORY_COUNT + category.index()] = ratio;
+    }
+
+    /// Writes every ratio in one comma separated list
+    pub fn to_text(&self) -> String {
+        CellProbabilityModel::weights_to_text(&self.ratios)
+    }
+
+    pub fn from_text(text: &str) -> Option<NeighborWeights> {
+        let ratios = parse_weights(text)?;
+        if ratios.len() != CELL_CATEGORY_COUNT * CELL_CATEGORY_COUNT {
+            return None;
+        }
+
+        Some(NeighborWeights { ratios })
+    }
+}
+
+/// Reads a comma separated list of numbers, like 0.1,0.2,0
+pub fn parse_weights(text: &str) -> Option<Vec<f64>> {
+    text.split(',').map(|weight| weight.trim().parse::<f64>().ok()).collect()
+}
+
+/// Learns cell weights from example dungeons, by counting how often each cell type shows up
+#[derive(Debug, PartialEq, Clone)]
+pub struct CellProbabilityFitter {
+    /// How many times each cell type was seen, in DUNGEON_CELL_REGISTRY order
+    type_counts: Vec<usize>,
+    /// How many times each category was seen next to each other category, indexed like NeighborWeights
+    neighbor_counts: Vec<usize>,
+    example_count: usize,
+}
+
+impl Default for CellProbabilityFitter {
+    fn default() -> Self {
+        CellProbabilityFitter::new()
+    }
+}
+
+impl CellProbabilityFitter {
+    pub fn new() -> CellProbabilityFitter {
+        CellProbabilityFitter {
+            type_counts: vec![0; DUNGEON_CELL_REGISTRY.len()],
+            neighbor_counts: vec![0; CELL_CATEGORY_COUNT * CELL_CATEGORY_COUNT],
+            example_count: 0,
+        }
+    }
+
+    /// Counts every settled cell in an example. Unsettled cells are skipped, so a partly painted grid still counts.
+    /// Cells with no possible types, like the ones Clean removes, count as None.
+    /// Returns an error, and counts nothing, if no cells are settled.
+    pub fn add_example(&mut self, example: &HexGrid<DungeonCellType>) -> Result<(), String> {
+        let registry = example.get_registry();
+        let cell_type = |cell: &PendingCell| {
+            if cell.possible_types.is_empty() { Some(DungeonCellType::None) } else { cell.settled_type().map(|index| *registry.get(index)) }
+        };
+        let settled_type = |location: &GridLocation| example.get_cell(location).and_then(cell_type);
+
+        if example.cells().iter().all(|cell| cell_type(cell).is_none()) {
+            return Err(String::from("The example doesn't have any settled cells"));
+        }
+
+        for index in 0..example.cells().len() {
+            let location = example.location_of(index);
+            let cell_type = match settled_type(&location) {
+                None => continue,
+                Some(cell_type) => cell_type,
+            };
+
+            self.type_counts[DUNGEON_CELL_REGISTRY.index_of(&cell_type).unwrap()] += 1;
+
+            let category = CellCategory::of(&cell_type);
+            for neighbor in location.get_neighbors().iter().filter_map(settled_type) {
+                self.neighbor_counts[CellCategory::of(&neighbor).index() * CELL_CATEGORY_COUNT + category.index()] += 1;
+            }
+        }
+
+        self.example_count += 1;
+
+        Ok(())
+    }
+
+    pub fn get_example_count(&self) -> usize {
+        self.example_count
+    }
+
+    /// How many times a cell type has been seen across every example
+    pub fn get_type_count(&self, cell_type: &DungeonCellType) -> usize {
+        self.type_counts[DUNGEON_CELL_REGISTRY.index_of(cell_type).unwrap()]
+    }
+
+    /// Estimates weights from the examples so far. Every type in a category shares a weight, so each category's weight
+    /// is the average frequency of its types, scaled so the largest weight is 1. With neighbors, it also estimates how
+    /// much each category's neighbors change its odds.
+    /// Returns None until an example has been added.
+    pub fn fit(&self, include_neighbors: bool) -> Option<CellProbabilityModel> {
+        if self.example_count == 0 {
+            return None;
+        }
+
+        let mut category_counts = [0usize; CELL_CATEGORY_COUNT];
+        for (index, count) in self.type_counts.iter().enumerate() {
+            category_counts[CellCategory::of(DUNGEON_CELL_REGISTRY.get(index)).index()] += count;
+        }
+
+        let categories = CellCategory::all();
+        let frequencies: Vec<f64> = categories.iter()
+            .map(|category| category_counts[category.index()] as f64 / category.type_count() as f64)
+            .collect();
+        let max_frequency = frequencies.iter().cloned().fold(0.0, f64::max);
+
+        let mut model = CellProbabilityModel::default();
+        for (category, frequency) in categories.iter().zip(frequencies) {
+            model.set_weight(*category, frequency / max_frequency);
+        }
+
+        if include_neighbors {
+            model.neighbor_weights = Some(self.fit_neighbors(&category_counts));
+        }
+
+        Some(model)
+    }
+
+    /// Compares how often each category shows up next to each neighbor with how often it shows up at all. Every count
+    /// gets 1 added, so pairs that were never seen make a category less likely rather than impossible.
+    fn fit_neighbors(&self, category_counts: &[usize; CELL_CATEGORY_COUNT]) -> NeighborWeights {
+        let smoothed_share = |count: usize, total: usize| (count as f64 + 1.0) / (total as f64 + CELL_CATEGORY_COUNT as f64);
+        let total: usize = category_counts.iter().sum();
+
+        let mut neighbor_weights = NeighborWeights::new();
+        for neighbor in CellCategory::all() {
+            let counts = &self.neighbor_counts[neighbor.index() * CELL_CATEGORY_COUNT..(neighbor.index() + 1) * CELL_CATEGORY_COUNT];
+            let neighbor_total: usize = counts.iter().sum();
+
+            for category in CellCategory::all() {
+                let ratio = smoothed_share(counts[category.index()], neighbor_total) / smoothed_share(category_counts[category.index()], total);
+                neighbor_weights.set_ratio(neighbor, category, ratio);
+            }
+        }
+
+        neighbor_weights
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+
+    /// A row of three cells: two Nones, then a room with no connections
+    fn example_row() -> WaveFunctionCollapseContext<DungeonCellType> {
+        let mut wfc = WaveFunctionCollapseContext::with_registry(1, 3, Arc::new(DUNGEON_CELL_REGISTRY.clone()));
+        wfc.apply_fixed_types(vec![
+            (GridLocation::new(0, 0), vec![DungeonCellType::None]),
+            (GridLocation::new(0, 1), vec![DungeonCellType::None]),
+            (GridLocation::new(0, 2), vec![DungeonCellType::Room(CellConnections::none())]),
+        ]);
+
+        wfc
+    }
+
+    #[test]
+    fn a_fitter_without_examples_fits_nothing() {
+        assert_eq!(CellProbabilityFitter::new().fit(false), None);
+    }
+
+    #[test]
+    fn examples_without_settled_cells_are_refused() {
+        let unsettled = WaveFunctionCollapseContext::with_registry(2, 2, Arc::new(DUNGEON_CELL_REGISTRY.clone()));
+        let mut fitter = CellProbabilityFitter::new();
+
+        assert!(fitter.add_example(unsettled.get_grid()).is_err());
+        assert_eq!(fitter.get_example_count(), 0);
+    }
+
+    #[test]
+    fn fitted_weights_follow_how_often_each_category_shows_up() {
+        let mut fitter = CellProbabilityFitter::new();
+        fitter.add_example(example_row().get_grid()).unwrap();
+        let model = fitter.fit(false).unwrap();
+
+        assert_eq!(model.none, 1.0);
+        assert_eq!(model.rooms[0], 0.5);
+        assert!(model.halls.iter().all(|weight| *weight == 0.0));
+        assert_eq!(model.neighbor_weights, None);
+    }
+
+    #[test]
+    fn categories_never_seen_together_are_less_likely_together() {
+        let mut fitter = CellProbabilityFitter::new();
+        fitter.add_example(example_row().get_grid()).unwrap();
+        let neighbor_weights = fitter.fit(true).unwrap().neighbor_weights.unwrap();
+
+        let room = CellCategory::Room(0);
+        assert!(neighbor_weights.get_ratio(room, room) < neighbor_weights.get_ratio(room, CellCategory::None));
+    }
+}
@@ -1,10 +1,17 @@
-use std::ops::Index;
-use std::{rc::Rc, cell::Cell};
-use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
 use lazy_static::lazy_static;
-use gloo_console::{error, log};
-use ran::{set_seeds, Rnum};
+#[cfg(target_arch = "wasm32")]
+use gloo_console::log;
 use crate::wfc::*;
+use crate::wfc::topology::*;
+use crate::wfc::hex::*;
+use crate::wfc::random::*;
+use crate::generation_fields::cell_probability::*;
+use crate::generation_fields::dungeon_config::*;
+use crate::generation_fields::replay::*;
+use crate::generation_fields::room_shape::*;
+use crate::generation_fields::validator::*;
 use std::collections::VecDeque;
 
 /// Whether the cells at each of the six neighbors connect
@@ -131,21 +138,19 @@ impl CellConnections {
     }
 
     /// Each element of cons_a or'd with the matching element of cons_b
-    pub fn or(cons_a: &Vec<bool>, cons_b: &Vec<bool>) -> Vec<bool> {
-        let mut index = 0;
-        cons_a.into_iter().map(|val| {
-            let result = *val || cons_b[index];
-            index += 1;
-            result
-        }).collect()
+    pub fn or(cons_a: &[bool], cons_b: &[bool]) -> Vec<bool> {
+        cons_a.iter().zip(cons_b.iter()).map(|(a, b)| *a || *b).collect()
     }
 
     // Gets the index of the opposite side (e.g BOTTOM_RIGHT would return TOP_LEFT)
     pub fn opposite_index(index: usize) -> usize {
-        (index + 3) % 6
+        DUNGEON_TOPOLOGY.opposite_direction(index)
     }
 }
 
+/// Dungeons are laid out on pointy top hexes, which is what the connection order above follows
+pub const DUNGEON_TOPOLOGY: HexTopology = HexTopology::POINTY_TOP;
+
 /// The potential Dungeon cells
 #[derive(Debug, Eq, Copy, Clone, PartialEq)]
 pub enum DungeonCellType {
@@ -167,6 +172,15 @@ impl DungeonCellType {
 
         types
     }
+
+    /// The directions this cell connects in. None connects nowhere.
+    pub fn connections(&self) -> CellConnections {
+        match self {
+            DungeonCellType::None => CellConnections::none(),
+            DungeonCellType::Hall(connections) => *connections,
+            DungeonCellType::Room(connections) => *connections,
+        }
+    }
 }
 
 fn all_halls() -> Vec<DungeonCellType> {
@@ -177,8 +191,6 @@ fn all_rooms() -> Vec<DungeonCellType> {
     CellConnections::all_possible().iter().map(|connection| DungeonCellType::Room(*connection)).collect()
 }
 
-type CellProbabilities = Vec<(DungeonCellType, f64)>;
-
 lazy_static! {
     /// The total numhber of possible dunceon cell types
     pub static ref NUM_POSSIBLE_DUNGEON_CELLS: usize = {
@@ -193,455 +205,608 @@ lazy_static! {
         all_rooms()
     };
 
-    pub static ref CELL_PROBABILITIES: CellProbabilities = {
-        let mut probabilities = vec![(DungeonCellType::None, 0.1f64)];
+    /// Every dungeon cell type, indexed the same as DungeonCellType::all()
+    pub static ref DUNGEON_CELL_REGISTRY: TypeRegistry<DungeonCellType> = {
+        TypeRegistry::new(&DungeonCellType::all())
+    };
+
+    /// For each direction, the set of cell types that connect in that direction
+    static ref CONNECTING_TYPES: Vec<TypeSet> = {
+        (0..6).map(|direction| {
+            let mut connecting_types = DUNGEON_CELL_REGISTRY.none();
+            for (index, cell_type) in DUNGEON_CELL_REGISTRY.types().iter().enumerate() {
+                if cell_type.connections().to_vec()[direction] {
+                    connecting_types.insert(index);
+                }
+            }
 
-        probabilities.append(&mut all_halls().into_iter().map(|hall| {
-            let num_connections = match hall {
-                DungeonCellType::Hall(cons) => cons.count(),
-                _ => 0,
-            } as f64;
+            connecting_types
+        }).collect()
+    };
+}
 
-            let probability = ((4.0 - num_connections).abs() / 6.0) * 0.7;
+/// What a stage wants the generator to do after it steps
+#[derive(Debug, Eq, Copy, Clone, PartialEq)]
+pub enum StageStatus {
+    /// Step the stage again next, without settling the cells it changed first
+    Working,
+    /// Settle the cells the stage changed, then step it again
+    Settle,
+    /// Settle the cells the stage changed, then move on to the next stage
+    Done,
+}
 
-            (hall, if num_connections > 2.0 { 0.0 } else { probability })
-        }).collect::<Vec<(DungeonCellType, f64)>>());
+/// One part of generating a dungeon, like placing rooms or forging the path. A generator runs its stages in order, a small
+/// step at a time, letting WFC settle whatever a stage changes in between.
+pub trait GenerationStage: fmt::Debug {
+    /// Shown as the generator's state while the stage runs
+    fn get_name(&self) -> &str;
 
-        probabilities.append(&mut all_rooms().into_iter().map(|room| {
-            let num_connections = match room {
-                DungeonCellType::Room(cons) => cons.count(),
-                _ => 0,
-            } as f64;
+    /// Does the next small piece of the stage's work. An error leaves the stage where it was, so it's stepped again next time.
+    fn step(&mut self, generator: &mut DungeonGenerator) -> Result<StageStatus, DungeonError>;
 
-            let probability = ((4.0 - num_connections) / 6.0) * 0.1;
+    /// Roughly how much of the stage's work is done, from 0 to 1
+    fn get_progress(&self, _generator: &DungeonGenerator) -> f64 {
+        0.0
+    }
 
-            (room, if num_connections > 2.0 { 0.0 } else { probability })
-        }).collect::<Vec<(DungeonCellType, f64)>>());
+    /// How many steps to take at a time when animating the stage. Stages made of lots of tiny steps can go faster.
+    fn get_steps_per_frame(&self) -> usize {
+        1
+    }
 
-        probabilities
-    };
+    /// Copies the stage, including how far along it is, so snapshots can rewind it
+    fn clone_stage(&self) -> Box<dyn GenerationStage>;
 }
 
-/// The different ways rooms can be shaped
-#[derive(Debug, Eq, Copy, Clone, PartialEq)]
-pub enum DungeonRoomShape {
-    /// A single-cell room
-    Single(CellConnections),
-    /// A cluster of three cells. Order is peak, bottom left, bottom right
-    Cluster3(CellConnections, CellConnections, CellConnections),
+impl Clone for Box<dyn GenerationStage> {
+    fn clone(&self) -> Self {
+        self.clone_stage()
+    }
 }
 
-/// A descriptor for generating a room in the dungeon
-#[derive(Debug, Eq, Copy, Clone, PartialEq)]
-pub struct DungeonRoom {
-    pub shape: DungeonRoomShape,
-    pub location: GridLocation,
+/// Why a dungeon couldn't be generated
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DungeonError {
+    /// The config has a setting out of range. This says which.
+    InvalidConfig(String),
+    /// A room was given a shape with no cells
+    EmptyRoom(GridLocation),
+    /// Part of a room would be off the grid
+    RoomOutOfBounds(GridLocation),
+    /// A stage needs the start cell, but no stage before it placed one
+    NoStart,
+    /// The start cell was left with nothing leading out of it, so there's no path to forge
+    StartNotConnecting(GridLocation),
+    /// The start cell leads off the grid or straight into the goal room, so the path can't leave it
+    PathBlocked(GridLocation),
+    /// The path leaving the start or a room's door got boxed in, or wandered too long without reaching where it was headed
+    PathStuck(GridLocation),
+    /// The finished dungeon didn't pass validation. This is the first problem found.
+    Invalid(DungeonProblem),
 }
 
-#[derive(Debug, Eq, Copy, Clone, PartialEq)]
-pub enum DungeonGeneratorState {
-    Init,
-    PlacedRooms,
-    ForgingPath,
-    Wfc,
-    Fill,
-    Clean,
-    Idle,
+impl fmt::Display for DungeonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DungeonError::InvalidConfig(message) => write!(f, "Invalid dungeon config: {}", message),
+            DungeonError::EmptyRoom(location) => write!(f, "The room at ({}, {}) has no cells", location.row, location.col),
+            DungeonError::NoStart => write!(f, "No start was placed before it was needed"),
+            DungeonError::RoomOutOfBounds(location) => {
+                write!(f, "The room at ({}, {}) doesn't fit on the grid", location.row, location.col)
+            },
+            DungeonError::StartNotConnecting(location) => {
+                write!(f, "The start cell at ({}, {}) doesn't connect to anything", location.row, location.col)
+            },
+            DungeonError::PathBlocked(location) => {
+                write!(f, "The path can't leave the start cell at ({}, {})", location.row, location.col)
+            },
+            DungeonError::PathStuck(location) => {
+                write!(f, "The path from ({}, {}) couldn't find its way", location.row, location.col)
+            },
+            DungeonError::Invalid(problem) => write!(f, "{}", problem),
+        }
+    }
+}
+
+impl std::error::Error for DungeonError {}
+
+/// The separate random streams a generator splits its seed into
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum DungeonRandomStream {
+    /// Where the start and goal go, and what shape they are
+    Rooms = 1,
+    /// How the path from the start to the goal wanders
+    Path = 2,
+    /// What each remaining cell collapses to
+    Fill = 3,
+    /// The seeds for attempts after the first
+    Attempts = 4,
+}
+
+/// How many decisions the generator can roll back when it hits a contradiction
+pub const DEFAULT_BACKTRACK_LIMIT: usize = 64;
+
+/// How many steps each leg of the path may take per cell on the grid before it gives up
+const MAX_PATH_STEPS_PER_CELL: usize = 4;
+
+/// Everything about a generator that changes as it runs, saved so it can be rewound. See DungeonGenerator::snapshot.
+#[derive(Debug, Clone)]
+pub struct DungeonSnapshot {
+    wfc: WfcSnapshot,
+    start_location: GridLocation,
+    goal_location: GridLocation,
+    goal_locations: Vec<GridLocation>,
+    goal_entrance_location: GridLocation,
+    rooms: Vec<DungeonRoom>,
+    stages: Vec<Box<dyn GenerationStage>>,
+    stage_index: usize,
+    settling: bool,
+    iteration_count: i32,
+    attempt: usize,
+    room_random: SeededRandom,
+    path_random: SeededRandom,
+    fill_random: SeededRandom,
+    observation_strategy: ObservationStrategy,
+    replay_action_count: usize,
 }
 
 /// Generates dungeons leveraging Wave Function Collapse
 pub struct DungeonGenerator {
     /// The wave function collapse context
     pub wfc: WaveFunctionCollapseContext<DungeonCellType>,
-    pub seed: u64,
+    /// The settings the generator was created with
+    config: DungeonGeneratorConfig,
     pub start_location: GridLocation,
     pub goal_location: GridLocation,
     pub goal_locations: Vec<GridLocation>,
     pub goal_entrance_location: GridLocation,
-    pub rows: usize,
-    pub cols: usize,
-    pub meander_factor: f64,
-    pub state: DungeonGeneratorState,
-    random: Rnum,
-    cursor_location: GridLocation,
+    /// The extra rooms scattered around the dungeon, not counting the start and goal
+    pub rooms: Vec<DungeonRoom>,
+    /// How the Fill state picks the next cell to collapse. This starts out as the config's, but can be switched.
+    pub observation_strategy: ObservationStrategy,
+    /// The stages generation runs through, in order
+    stages: Vec<Box<dyn GenerationStage>>,
+    /// Which stage is running. Once every stage is done, this is the stage count.
+    stage_index: usize,
+    /// Whether WFC is settling the cells the current stage changed before the stage steps again
+    settling: bool,
+    /// Random streams for each part of generation, split from the seed so that, say, a longer path doesn't change the fill
+    room_random: SeededRandom,
+    path_random: SeededRandom,
+    fill_random: SeededRandom,
+    /// Every external action so far, for the replay log
+    replay_actions: Vec<ReplayAction>,
     iteration_count: i32,
-    valid_path_cells: Vec<Rc<RefCell<PendingCell<DungeonCellType>>>>,
-    unfilled_cells: Vec<Rc<RefCell<PendingCell<DungeonCellType>>>>,
+    /// Which attempt this is, counting from 0. Each attempt after the first uses a seed derived from the config's.
+    attempt: usize,
+    /// The grid and stages before anything ran, so a new attempt can start over
+    initial_wfc: WfcSnapshot,
+    initial_stages: Vec<Box<dyn GenerationStage>>,
 }
 
 impl DungeonGenerator {
-    pub fn new(rows: usize, cols: usize) -> DungeonGenerator {
-        DungeonGenerator {
-            wfc: WaveFunctionCollapseContext::<DungeonCellType>::new(rows, cols, &DungeonCellType::all()),
-            seed: 1u64,
+    /// Creates a generator with the given settings, running the default stages. Errors if the config isn't valid.
+    pub fn new(config: DungeonGeneratorConfig) -> Result<DungeonGenerator, DungeonError> {
+        DungeonGenerator::with_stages(config, DungeonGenerator::default_stages())
+    }
+
+    /// Creates a generator that runs the given stages in order instead of the default ones.
+    /// Replays always use the default stages, so they won't match a generator made this way.
+    pub fn with_stages(config: DungeonGeneratorConfig, stages: Vec<Box<dyn GenerationStage>>) -> Result<DungeonGenerator, DungeonError> {
+        config.validate().map_err(DungeonError::InvalidConfig)?;
+
+        let mut wfc = WaveFunctionCollapseContext::<DungeonCellType>::with_registry(config.rows, config.cols, Arc::new(DUNGEON_CELL_REGISTRY.clone()));
+        wfc.set_backtrack_limit(config.backtrack_limit);
+
+        let mut generator = DungeonGenerator {
+            initial_wfc: wfc.snapshot(),
+            initial_stages: stages.clone(),
+            wfc,
             start_location: GridLocation::new(-1, -1),
             goal_location: GridLocation::new(-1, -1),
             goal_locations: vec![],
             goal_entrance_location: GridLocation::new(-1, -1),
-            rows,
-            cols,
-            meander_factor: 0.7,
-            state: DungeonGeneratorState::Init,
-            random: Rnum::newf64(),
-            cursor_location: GridLocation::new(-1, -1),
+            rooms: vec![],
+            observation_strategy: config.observation_strategy,
+            stages,
+            stage_index: 0,
+            settling: false,
+            room_random: SeededRandom::new(0),
+            path_random: SeededRandom::new(0),
+            fill_random: SeededRandom::new(0),
+            replay_actions: vec![],
             iteration_count: 0,
-            valid_path_cells: vec![],
-            unfilled_cells: vec![],
-        }
+            attempt: 0,
+            config,
+        };
+        generator.reset_to_attempt(0);
+
+        Ok(generator)
     }
 
-    /// Steps forward one step through generation state
-    pub fn step(&mut self) {
-        match self.state {
-            DungeonGeneratorState::Init => {
-                log!("DGEN: INIT");
+    /// Places the start and goal, scatters the extra rooms, forges paths joining them all, fills in everything else, then
+    /// removes what can't be reached
+    pub fn default_stages() -> Vec<Box<dyn GenerationStage>> {
+        vec![
+            Box::new(PlaceRoomsStage {}),
+            Box::new(ScatterRoomsStage { attempted_count: 0 }),
+            Box::new(ForgePathStage { cursor_location: None, path: vec![], leg: 0, leg_start: 0, leg_steps: 0 }),
+            Box::new(FillStage {}),
+            Box::new(CleanStage {}),
+        ]
+    }
 
-                set_seeds(self.seed);
+    pub fn get_config(&self) -> &DungeonGeneratorConfig {
+        &self.config
+    }
 
-                self.goal_location = self.random_interior_location();
+    /// Steps forward one step through generation: either the current stage's next step, or settling what it changed.
+    /// Errors leave the generator stuck where it is, so stepping again gives the same error. Only steps that succeed are
+    /// recorded for replays.
+    pub fn step(&mut self) -> Result<(), DungeonError> {
+        if self.settling || self.stage_index >= self.stages.len() {
+            #[cfg(target_arch = "wasm32")]
+            log!("DGEN: WFC");
+            self.wfc.iterate_queue(&mut DungeonGenerator::collapse_cell);
+            self.settling = !self.wfc.get_queue().is_empty();
+        } else {
+            // The stage needs the whole generator, so it's taken out of the list while it steps
+            let mut stages = std::mem::take(&mut self.stages);
+            let result = stages[self.stage_index].step(self);
+            self.stages = stages;
+
+            match result? {
+                StageStatus::Working => {},
+                StageStatus::Settle => self.settling = !self.wfc.get_queue().is_empty(),
+                StageStatus::Done => {
+                    self.stage_index += 1;
+                    self.settling = !self.wfc.get_queue().is_empty();
+                },
+            }
+        }
 
-                self.goal_entrance_location = self.goal_location.get_neighbors()[0];
+        self.replay_actions.push(ReplayAction::Step);
+        self.iteration_count += 1;
 
-                let goal_in_small_room = self.random_bool_default();
-                let mut goal_connections = CellConnections::new(true, false, false, false, false, false);
+        Ok(())
+    }
 
-                self.goal_locations = if goal_in_small_room {
-                    self.add_room(&DungeonRoom { shape: DungeonRoomShape::Single(goal_connections), location: self.goal_location })
-                } else {
-                    goal_connections.bottom_left = true;
-                    goal_connections.bottom_right = true;
-                    self.add_room(&DungeonRoom { shape: DungeonRoomShape::Cluster3(
-                        goal_connections,
-                        CellConnections::new(false, true, true, false, false, false),
-                        CellConnections::new(true, false, false, false, false, true),
-                    ), location: self.goal_location })
-                };
+    /// Run through the complete generation process. If the config allows more than one attempt, the finished dungeon is
+    /// validated too, and any failed attempt starts over with the next attempt's seed. Errors if the last attempt fails.
+    pub fn generate(&mut self) -> Result<(), DungeonError> {
+        loop {
+            let result = self.generate_attempt();
+            if result.is_err() && self.can_retry() {
+                #[cfg(target_arch = "wasm32")]
+                log!("DGEN: RETRYING");
+                self.start_attempt(self.attempt + 1);
+            } else {
+                return result;
+            }
+        }
+    }
 
-                self.start_location = self.random_interior_location();
+    /// Runs the current attempt to the end, stopping at the first error
+    fn generate_attempt(&mut self) -> Result<(), DungeonError> {
+        while self.can_do_more_work() {
+            self.step()?;
+        }
 
-                while self.goal_locations.contains(&self.start_location) {
-                    self.start_location = self.random_interior_location();
-                }
+        self.check_attempt()
+    }
 
-                let start_in_hall = self.random_bool_default();
-
-                if start_in_hall {
-                    self.wfc.apply_types(vec![(
-                        self.start_location,
-                        vec![DungeonCellType::Hall(self.random_connections())],
-                    )]);
-                } else {
-                    // TODO: Don't try to connect to edge
-                    let mut start_connections = CellConnections::none().to_vec();
-                    start_connections[self.random_in_range(0f64, 5f64) as usize] = true;
-                    self.add_room(&DungeonRoom { shape: DungeonRoomShape::Single(CellConnections::from_vec(start_connections)), location: self.start_location });
-                }
+    /// Validates the finished dungeon if the config allows more than one attempt, erroring with the first problem.
+    /// With a single attempt there's nothing to retry, so this is always fine.
+    pub fn check_attempt(&self) -> Result<(), DungeonError> {
+        if self.config.generation_attempts <= 1 {
+            return Ok(());
+        }
 
-                self.state = DungeonGeneratorState::PlacedRooms;
-            },
-            DungeonGeneratorState::PlacedRooms => {
-                log!("DGEN: PLACED_ROOMS");
-                self.cursor_location = self.start_location;
-                self.state = DungeonGeneratorState::Wfc;
-            },
-            DungeonGeneratorState::ForgingPath => {
-                log!("DGEN: FORGING_PATH");
+        match self.validate().first() {
+            Some(problem) => Err(DungeonError::Invalid(*problem)),
+            None => Ok(()),
+        }
+    }
 
-                let neighbor_locations = self.cursor_location.get_neighbors();
+    /// Whether the config allows another attempt after this one
+    pub fn can_retry(&self) -> bool {
+        self.attempt + 1 < self.config.generation_attempts
+    }
 
-                let next_location_index = if self.cursor_location == self.start_location {
-                    let start_cell = self.wfc.get_grid().get_cell(&self.start_location).unwrap();
-                    let start_connections = match start_cell.borrow().possible_types[0] {
-                        DungeonCellType::Hall(cons) => cons,
-                        DungeonCellType::Room(cons) => cons,
-                        _ => panic!("Start cell is not a connecting type"),
-                    }.to_vec();
+    /// Throws out everything generated so far and starts over as the given attempt, recording it in the replay log.
+    /// The fill strategy is kept, since it's the user's choice rather than part of the attempt.
+    pub fn start_attempt(&mut self, attempt: usize) {
+        self.replay_actions.push(ReplayAction::StartAttempt(attempt));
+        self.reset_to_attempt(attempt);
+    }
 
-                    start_connections.iter().position(|i| *i).unwrap()
-                } else {
-                    let neighbor_distances: Vec<i64> = neighbor_locations.iter().map(|loc| {
-                        (self.goal_entrance_location.row as i64 - loc.row as i64).abs() +
-                        (self.goal_entrance_location.col as i64 - loc.col as i64).abs()
-                    }).collect();
+    /// Puts everything back the way it was before any stage ran, with each random stream split from the attempt's seed.
+    /// Stages never reseed the streams, so dropping or reordering stages still generates from the config's seed.
+    fn reset_to_attempt(&mut self, attempt: usize) {
+        self.attempt = attempt;
+        self.wfc.restore(&self.initial_wfc);
+        self.stages = self.initial_stages.clone();
+        self.stage_index = 0;
+        self.settling = false;
+        self.start_location = GridLocation::new(-1, -1);
+        self.goal_location = GridLocation::new(-1, -1);
+        self.goal_locations = vec![];
+        self.goal_entrance_location = GridLocation::new(-1, -1);
+        self.rooms = vec![];
+
+        let random = SeededRandom::new(self.get_attempt_seed());
+        self.room_random = random.substream(DungeonRandomStream::Rooms as u64);
+        self.path_random = random.substream(DungeonRandomStream::Path as u64);
+        self.fill_random = random.substream(DungeonRandomStream::Fill as u64);
+    }
 
+    /// Which attempt this is, counting from 0
+    pub fn get_attempt(&self) -> usize {
+        self.attempt
+    }
 
-                    let min_distance = neighbor_distances.iter().min().unwrap();
-                    let closest_cell = neighbor_distances.iter().position(|val| val == min_distance).unwrap();
+    /// The seed the current attempt generates from. See attempt_seed.
+    pub fn get_attempt_seed(&self) -> u64 {
+        DungeonGenerator::attempt_seed(self.config.seed, self.attempt)
+    }
 
-                    // Evilness warning: Random selection is how we avoid not passing the if statement later on when it overlaps the start cell, etc.
-                    let choose_closest = self.random_bool(self.meander_factor);
-                    if choose_closest {
-                        closest_cell
-                    } else {
-                        self.random_in_range(0f64, 5f64) as usize
-                    }
-                };
-
-                let next_location = neighbor_locations[next_location_index];
-                let next_cell = self.wfc.get_grid().get_cell(&next_location);
-                if next_location != self.start_location && !self.goal_locations.contains(&next_location) && next_cell.is_some() {
-                    let next_cell = next_cell.unwrap();
-                    let mut next_required_connections = CellConnections::none().to_vec();
-                    next_required_connections[CellConnections::opposite_index(next_location_index)] = true;
-                    if next_location == self.goal_entrance_location {
-                        // Force connection to goal. For now, the goal entrance is always top left
-                        next_required_connections[CellConnections::BOTTOM_RIGHT] = true;
-                    }
+    /// The seed for an attempt. The first attempt uses the seed as it is, and the rest derive theirs from it, so any attempt
+    /// can be reproduced from the original seed and its number.
+    pub fn attempt_seed(seed: u64, attempt: usize) -> u64 {
+        if attempt == 0 {
+            seed
+        } else {
+            SeededRandom::new(seed).substream(DungeonRandomStream::Attempts as u64).substream(attempt as u64).next_u64()
+        }
+    }
 
-                    let next_cell_types = next_cell.borrow().possible_types.clone().into_iter().filter(|cell_type| {
-                        match cell_type {
-                            DungeonCellType::None => false,
-                            DungeonCellType::Room(_) => false,
-                            DungeonCellType::Hall(connections) => {
-                                let connections_vec = connections.to_vec();
-                                for i in 0..connections_vec.len() {
-                                    if next_required_connections[i] && !connections_vec[i] {
-                                        return false;
-                                    }
-                                }
+    /// More generation can be done, whether it's dungeon steps or wfc
+    pub fn can_do_more_work(&self) -> bool {
+        self.stage_index < self.stages.len() || !self.wfc.get_queue().is_empty()
+    }
 
-                                true
-                            },
-                        }
-                    }).collect();
-
-                    self.wfc.apply_types(vec![(
-                        next_location,
-                        next_cell_types,
-                    )]);
-
-                    if self.valid_path_cells.len() > 0 {
-                        // make sure the previous cell connects here
-                        let previous_cell = self.valid_path_cells.last().unwrap().clone();
-                        let mut previous_required_connections = CellConnections::none().to_vec();
-                        previous_required_connections[next_location_index] = true;
-
-                        let previous_cell_types: Vec<DungeonCellType> = previous_cell.borrow().possible_types.clone().into_iter().filter(|cell_type| {
-                            match cell_type {
-                                DungeonCellType::None => false,
-                                DungeonCellType::Room(_) => false,
-                                DungeonCellType::Hall(connections) => {
-                                    let connections_vec = connections.to_vec();
-                                    for i in 0..connections_vec.len() {
-                                        if previous_required_connections[i] && !connections_vec[i] {
-                                            return false;
-                                        }
-                                    }
-
-                                    true
-                                },
-                            }
-                        }).collect();
+    /// Adds a room to the dungeon. Its cells are fixed, so backtracking never rolls them back out from under `rooms` and the
+    /// goal and start locations. Returns all locations of the room, or an error if it has no cells or any of it would be off
+    /// the grid.
+    pub fn add_room(&mut self, room: &DungeonRoom) -> Result<Vec<GridLocation>, DungeonError> {
+        let locations = room.get_locations();
+        if locations.is_empty() {
+            return Err(DungeonError::EmptyRoom(room.location));
+        }
 
-                        let previous_location = previous_cell.borrow().location;
+        if locations.iter().any(|location| self.wfc.get_grid().get_cell(location).is_none()) {
+            return Err(DungeonError::RoomOutOfBounds(room.location));
+        }
 
-                        self.wfc.apply_types(vec![(
-                            previous_location,
-                            previous_cell_types,
-                        )]);
-                    }
+        self.wfc.apply_fixed_types(locations.iter().zip(room.get_cell_connections()).map(|(location, connections)| {
+            (*location, vec![DungeonCellType::Room(connections)])
+        }).collect());
 
-                    self.valid_path_cells.push(next_cell.clone());
+        Ok(locations)
+    }
 
-                    self.cursor_location = next_location;
-                    if self.cursor_location == self.goal_entrance_location {
-                        self.state = DungeonGeneratorState::Wfc;
-                    }
-                }
-            },
-            DungeonGeneratorState::Wfc => {
-                log!("DGEN: WFC");
-                self.wfc.iterate_queue(DungeonGenerator::collapse_cell);
-                if self.wfc.get_queue().is_empty() {
-                    if self.valid_path_cells.is_empty() {
-                        self.state = DungeonGeneratorState::ForgingPath;
-                    } else {
-                        self.unfilled_cells =
-                            self.wfc.get_grid().grid.iter()
-                            .flatten()
-                            .filter(|cell| !cell.borrow().is_settled())
-                            .map(|cell| cell.clone())
-                            .collect();
-
-                        self.state = DungeonGeneratorState::Fill;
-                    }
-                }
-            },
-            DungeonGeneratorState::Fill => {
-                log!("DGEN: FILL");
-
-                if self.wfc.is_settled() {
-                    self.state = DungeonGeneratorState::Clean;
-                } else {
-                    let cell_index = self.random_in_range(0.0, (self.unfilled_cells.len() as f64) - 1.0) as usize;
-                    let cell = self.unfilled_cells[cell_index].clone();
-                    self.apply_random_cell_type(&vec![cell]);
-                    self.state = DungeonGeneratorState::Wfc;
-                }
-            },
-            DungeonGeneratorState::Clean => {
-                let start_cell = self.wfc.get_grid().get_cell(&self.start_location).unwrap();
-                let mut conencted_locations = vec![start_cell.borrow().location];
-
-                type DepthQueue = VecDeque::<Rc<RefCell<PendingCell<DungeonCellType>>>>;
-                let mut depth_queue = DepthQueue::new();
-                depth_queue.push_back(start_cell.clone());
-
-                while !depth_queue.is_empty() {
-                    let cell = depth_queue.pop_front().unwrap();
-                    let connections = match cell.borrow().possible_types[0] {
-                        DungeonCellType::Hall(cons) => cons,
-                        DungeonCellType::Room(cons) => cons,
-                        _ => CellConnections::none(),
-                    }.to_vec();
+    /// Whether a room fits at the location: clear of the grid's edges, and at least the config's room spacing away from the
+    /// start, the goal, the goal's entrance and every other room
+    pub fn can_place_room(&self, shape: &DungeonRoomShape, location: GridLocation) -> bool {
+        let room = DungeonRoom { shape: shape.clone(), location, doors: vec![] };
+        let locations = room.get_locations();
+
+        let is_clear_of_edges = locations.iter().all(|location| {
+            location.row >= 1 && location.row < self.config.rows as i64 - 1 && location.col >= 1 && location.col < self.config.cols as i64 - 1
+        });
+
+        if !is_clear_of_edges {
+            return false;
+        }
 
-                    let mut neighbor_index = 0;
-                    let mut conencted_neighbors: Vec::<GridLocation> = cell.borrow().location.get_neighbors().into_iter()
-                        .filter(|loc| {
-                            let result = connections[neighbor_index] && !conencted_locations.contains(loc);
-                            neighbor_index += 1;
-                            result
-                        })
-                        .collect();
+        let mut taken_locations = self.goal_locations.clone();
+        taken_locations.push(self.goal_entrance_location);
+        taken_locations.push(self.start_location);
+        for other_room in self.rooms.iter() {
+            taken_locations.append(&mut other_room.get_locations());
+        }
 
-                    let mut conencted_neighbor_cells: DepthQueue = conencted_neighbors.clone().into_iter()
-                        .map(|loc| self.wfc.get_grid().get_cell(&loc).unwrap().clone()).collect();
+        let taken_coords: Vec<CubeCoord> = taken_locations.iter().map(CubeCoord::from_location).collect();
+        locations.iter().map(CubeCoord::from_location).all(|coord| {
+            taken_coords.iter().all(|taken| coord.distance(taken) >= self.config.room_spacing as i64)
+        })
+    }
 
-                    depth_queue.append(&mut conencted_neighbor_cells);
-                    conencted_locations.append(&mut conencted_neighbors);
-                }
+    /// Errors if no stage has placed the start on the grid yet, for stages that need it
+    fn check_start_placed(&self) -> Result<(), DungeonError> {
+        match self.wfc.get_grid().get_cell(&self.start_location) {
+            Some(_) => Ok(()),
+            None => Err(DungeonError::NoStart),
+        }
+    }
 
-                for row in 0..self.rows {
-                    for col in 0..self.cols {
-                        let loc = GridLocation::new(row as i64, col as i64);
+    /// Whether the location is part of one of the extra rooms
+    pub fn is_room_location(&self, location: &GridLocation) -> bool {
+        self.rooms.iter().any(|room| room.get_locations().contains(location))
+    }
 
-                        // If it doesn't connect to anything we can get to, remove the cell
-                        if !conencted_locations.contains(&loc) {
-                            self.wfc.get_grid().get_cell(&loc).unwrap().borrow_mut().possible_types = vec![];
-                        }
-                    }
-                }
+    /// Picks up to `count` different doors on the outside of a room placed at `location`, leaving out any that would lead off the grid
+    pub fn choose_doors(&mut self, shape: &DungeonRoomShape, location: GridLocation, count: usize) -> Vec<RoomDoor> {
+        let origin = CubeCoord::from_location(&location);
+        let mut candidates: Vec<RoomDoor> = shape.get_outer_boundary().into_iter().filter(|door| {
+            self.wfc.get_grid().get_cell(&(origin + door.get_outside_offset()).to_location()).is_some()
+        }).collect();
 
-                self.state = DungeonGeneratorState::Idle;
-            },
-            DungeonGeneratorState::Idle => {
-                if !self.wfc.get_queue().is_empty() {
-                    self.state = DungeonGeneratorState::Wfc;
-                }
-            },
+        let mut doors = vec![];
+        while doors.len() < count && !candidates.is_empty() {
+            doors.push(candidates.remove(self.room_random.index_below(candidates.len())));
         }
 
-        self.iteration_count += 1;
+        doors
     }
 
-    /// Run through the complete generation process
-    pub fn generate(&mut self) {
-        while self.can_do_more_work() {
-            self.step();
+    /// The running stage's name, or Wfc while settling, or Idle once every stage is done
+    pub fn debug_state(&self) -> &str {
+        if self.settling {
+            "Wfc"
+        } else {
+            match self.stages.get(self.stage_index) {
+                Some(stage) => stage.get_name(),
+                None => "Idle",
+            }
         }
     }
 
-    /// More generation can be done, whether it's dungeon steps or wfc
-    pub fn can_do_more_work(&self) -> bool {
-        self.state != DungeonGeneratorState::Idle || !self.wfc.get_queue().is_empty()
+    /// The names of every stage, in the order they run
+    pub fn get_stage_names(&self) -> Vec<String> {
+        self.stages.iter().map(|stage| stage.get_name().to_string()).collect()
     }
 
-    /// Adds a room to the dungeon
-    /// Returns all locations of the room.
-    pub fn add_room(&mut self, room: &DungeonRoom) -> Vec<GridLocation> {
-        let neighbor_locations = room.location.get_neighbors();
-        let neighbor_cells: Vec<MaybeCell<DungeonCellType>> = neighbor_locations.iter().map(|loc| self.wfc.get_grid().get_cell(&loc)).collect();
+    /// Which stage is running. Once every stage is done, this is the stage count.
+    pub fn get_stage_index(&self) -> usize {
+        self.stage_index
+    }
 
-        match room.shape {
-            DungeonRoomShape::Single(connections) => {
-                self.wfc.apply_types(vec![
-                    (room.location, vec![DungeonCellType::Room(connections)]),
-                ]);
+    /// Roughly how much of generation is done, from 0 to 1, counting each stage equally
+    pub fn get_progress(&self) -> f64 {
+        if self.stages.is_empty() {
+            return 1.0;
+        }
 
-                vec![room.location]
-            },
-            DungeonRoomShape::Cluster3(cons1, cons2, cons3) => {
-                if neighbor_cells[CellConnections::BOTTOM_LEFT].is_none() || neighbor_cells[CellConnections::BOTTOM_RIGHT].is_none() {
-                    panic!("Bad cell location adding room");
-                }
+        let stage_progress = match self.stages.get(self.stage_index) {
+            Some(stage) => stage.get_progress(self).clamp(0.0, 1.0),
+            None => 0.0,
+        };
 
-                self.wfc.apply_types(vec![
-                    (room.location, vec![DungeonCellType::Room(cons1)]),
-                    (neighbor_locations[CellConnections::BOTTOM_LEFT], vec![DungeonCellType::Room(cons2)]),
-                    (neighbor_locations[CellConnections::BOTTOM_RIGHT], vec![DungeonCellType::Room(cons3)]),
-                ]);
-
-                vec![
-                    room.location,
-                    neighbor_locations[CellConnections::BOTTOM_LEFT],
-                    neighbor_locations[CellConnections::BOTTOM_RIGHT],
-                ]
-            },
+        (self.stage_index as f64 + stage_progress) / self.stages.len() as f64
+    }
+
+    /// How many steps to take at a time when animating the current stage
+    pub fn get_steps_per_frame(&self) -> usize {
+        match self.stages.get(self.stage_index) {
+            Some(stage) => stage.get_steps_per_frame(),
+            None => 1,
         }
     }
 
-    pub fn debug_state(&self) -> &str {
-        match self.state {
-            DungeonGeneratorState::Init => "Init",
-            DungeonGeneratorState::PlacedRooms => "Placed Rooms",
-            DungeonGeneratorState::ForgingPath => "Forging Path",
-            DungeonGeneratorState::Wfc => "Wfc",
-            DungeonGeneratorState::Fill => "Fill",
-            DungeonGeneratorState::Clean => "Clean",
-            DungeonGeneratorState::Idle => "Idle",
+    /// Checks the grid for an unreachable goal, one-sided connections, contradictions and doors into nothing.
+    /// Only meaningful once generation is done.
+    pub fn validate(&self) -> Vec<DungeonProblem> {
+        DungeonValidator::new(self.start_location, self.goal_location).validate(self.wfc.get_grid())
+    }
+
+    /// Saves the grid, where generation is up to, and where each random stream is
+    pub fn snapshot(&self) -> DungeonSnapshot {
+        DungeonSnapshot {
+            wfc: self.wfc.snapshot(),
+            start_location: self.start_location,
+            goal_location: self.goal_location,
+            goal_locations: self.goal_locations.clone(),
+            goal_entrance_location: self.goal_entrance_location,
+            rooms: self.rooms.clone(),
+            stages: self.stages.clone(),
+            stage_index: self.stage_index,
+            settling: self.settling,
+            iteration_count: self.iteration_count,
+            attempt: self.attempt,
+            room_random: self.room_random.clone(),
+            path_random: self.path_random.clone(),
+            fill_random: self.fill_random.clone(),
+            observation_strategy: self.observation_strategy,
+            replay_action_count: self.replay_actions.len(),
         }
     }
 
-    fn random_interior_location(&self) -> GridLocation {
-        GridLocation::new(
-            self.random.rannum_in(1f64, (self.rows as f64) - 2f64).getf64().unwrap() as i64,
-            self.random.rannum_in(1f64, (self.cols as f64) - 2f64).getf64().unwrap() as i64,
-        )
+    /// Puts the generator back the way it was when the snapshot was taken, so stepping again picks up exactly where it left off
+    pub fn restore(&mut self, snapshot: &DungeonSnapshot) {
+        self.wfc.restore(&snapshot.wfc);
+        self.start_location = snapshot.start_location;
+        self.goal_location = snapshot.goal_location;
+        self.goal_locations = snapshot.goal_locations.clone();
+        self.goal_entrance_location = snapshot.goal_entrance_location;
+        self.rooms = snapshot.rooms.clone();
+        self.stages = snapshot.stages.clone();
+        self.stage_index = snapshot.stage_index;
+        self.settling = snapshot.settling;
+        self.iteration_count = snapshot.iteration_count;
+        self.attempt = snapshot.attempt;
+
+        self.room_random = snapshot.room_random.clone();
+        self.path_random = snapshot.path_random.clone();
+        self.fill_random = snapshot.fill_random.clone();
+        self.observation_strategy = snapshot.observation_strategy;
+
+        // Whatever was done after the snapshot didn't happen as far as the replay is concerned
+        self.replay_actions.truncate(snapshot.replay_action_count);
     }
 
-    fn random_location(&self) -> GridLocation {
-        GridLocation::new(
-            self.random.rannum_in(0f64, (self.rows as f64) - 1f64).getf64().unwrap() as i64,
-            self.random.rannum_in(0f64, (self.cols as f64) - 1f64).getf64().unwrap() as i64,
-        )
+    /// Applies types by hand, recording them in the replay log. Prefer this over applying types on wfc directly.
+    pub fn apply_types(&mut self, types: Vec<(GridLocation, Vec<DungeonCellType>)>) {
+        self.replay_actions.push(ReplayAction::ApplyTypes(types.clone()));
+        self.wfc.apply_types(types);
     }
 
-    fn random_bool(&self, likelihood: f64) -> bool {
-        self.random.rannum_in(0f64, 100f64).getf64().unwrap() <= (likelihood * 100f64)
+    /// Switches how the Fill state picks cells, recording it in the replay log
+    pub fn set_observation_strategy(&mut self, observation_strategy: ObservationStrategy) {
+        self.replay_actions.push(ReplayAction::SetObservationStrategy(observation_strategy));
+        self.observation_strategy = observation_strategy;
     }
 
-    fn random_bool_default(&self) -> bool {
-        self.random_bool(0.5f64)
+    /// Gets everything needed to rerun generation up to now. Once generation has finished, the log includes the final
+    /// grid's hash so a replay can be checked.
+    pub fn get_replay_log(&self) -> ReplayLog {
+        ReplayLog {
+            config: self.config.clone(),
+            actions: self.replay_actions.clone(),
+            final_grid_hash: if self.can_do_more_work() { None } else { Some(self.wfc.get_grid().content_hash()) },
+        }
     }
 
-    fn random_in_range(&self, min: f64, max: f64) -> f64 {
-        self.random.rannum_in(min, max).getf64().unwrap()
+    fn random_interior_location(&mut self) -> GridLocation {
+        GridLocation::new(
+            1 + self.room_random.index_below(self.config.rows - 2) as i64,
+            1 + self.room_random.index_below(self.config.cols - 2) as i64,
+        )
     }
 
-    fn random_connections(&self) -> CellConnections {
+    fn random_connections(&mut self) -> CellConnections {
         CellConnections::new(
-            self.random_bool_default(),
-            self.random_bool_default(),
-            self.random_bool_default(),
-            self.random_bool_default(),
-            self.random_bool_default(),
-            self.random_bool_default(),
+            self.room_random.chance(self.config.start_connection_chance),
+            self.room_random.chance(self.config.start_connection_chance),
+            self.room_random.chance(self.config.start_connection_chance),
+            self.room_random.chance(self.config.start_connection_chance),
+            self.room_random.chance(self.config.start_connection_chance),
+            self.room_random.chance(self.config.start_connection_chance),
         )
     }
 
-    fn apply_random_cell_type(&mut self, cells: &Vec<Rc<RefCell<PendingCell<DungeonCellType>>>>) {
-        self.wfc.apply_types(cells.into_iter().map(|cell| {
-            let location = cell.borrow().location;
-            let types = vec![self.get_random_cell_type(&cell.borrow().possible_types)];
-            (location, types)
-        }).collect());
+    fn apply_random_cell_type(&mut self, locations: &[GridLocation]) {
+        let grid = self.wfc.get_grid();
+        let registry = grid.get_registry();
+        let fill_random = &mut self.fill_random;
+        let types = locations.iter().map(|location| {
+            let cell = grid.get_cell(location).unwrap();
+            let neighbors: Vec<CellCategory> = location.get_neighbors().iter()
+                .filter_map(|neighbor| grid.get_cell(neighbor).and_then(|neighbor_cell| neighbor_cell.settled_type()))
+                .map(|index| CellCategory::of(registry.get(index)))
+                .collect();
+
+            let types = vec![DungeonGenerator::get_random_cell_type(registry, &cell.possible_types, &neighbors, &self.config, fill_random)];
+            (*location, types)
+        }).collect();
+
+        self.wfc.apply_types(types);
     }
 
-    fn get_random_cell_type(&self, possible_types: &Vec<DungeonCellType>) -> DungeonCellType {
-        if possible_types.len() == 0 {
+    fn get_random_cell_type(
+        registry: &TypeRegistry<DungeonCellType>,
+        possible_types: &TypeSet,
+        neighbors: &[CellCategory],
+        config: &DungeonGeneratorConfig,
+        random: &mut SeededRandom,
+    ) -> DungeonCellType {
+        let option_count = possible_types.count();
+
+        if option_count == 0 {
             return DungeonCellType::None
-        } else if possible_types.len() == 1 {
-            return possible_types[0];
+        } else if option_count == 1 {
+            return *registry.get(possible_types.first().unwrap());
         }
 
         type ProbabilityTriple = (DungeonCellType, f64, f64);
@@ -649,8 +814,9 @@ impl DungeonGenerator {
 
         // Track the running total across the array, so that the larger the type's relative value, the more "space" it takes up in the rolling area. When we
         // pick a number in the range between 0 and total, the highest index where the number is above the running total is the selection.
-        let probabilities: Vec<ProbabilityTriple> = CELL_PROBABILITIES.clone().into_iter()
-            .filter(|pair| possible_types.contains(&pair.0))
+        let probabilities: Vec<ProbabilityTriple> = possible_types.iter()
+            .map(|index| registry.get(index))
+            .map(|cell_type| (*cell_type, config.cell_weights.cell_type_weight_near(cell_type, neighbors)))
             .map(|pair| {
                 let start_value = total;
                 let triple = (pair.0, pair.1, start_value);
@@ -659,27 +825,19 @@ impl DungeonGenerator {
             })
             .collect();
 
-        let value = self.random_in_range(0f64, total);
-
-        for i in 0..probabilities.len() {
-            if value < probabilities[i].2 {
-                if i == 0 {
-                    panic!("Shouldn't be possible. probabilities[0].0 should be 0 and 0 is not less than 0.");
-                }
-                return probabilities[i - 1].0;
-            }
-        }
+        let value = random.range_f64(0f64, total);
 
-        probabilities.last().unwrap().0
+        // The first start value is 0, so there's always a match
+        probabilities.iter().rev().find(|probability| value >= probability.2).unwrap().0
     }
 
     /// The rules passed to the WFC solver
-    fn collapse_cell(loc: &GridLocation, cell: Rc<RefCell<PendingCell<DungeonCellType>>>, grid: &HexGrid<DungeonCellType>) -> bool {
-        let mut cell = cell.borrow_mut();
-        let initial_option_count = cell.possible_types.len();
+    fn collapse_cell(loc: &GridLocation, cell: &PendingCell, grid: &HexGrid<DungeonCellType>) -> Option<TypeSet> {
+        let mut possible_types = cell.possible_types.clone();
+        let initial_option_count = possible_types.count();
 
         if initial_option_count <= 1 {
-            return false;
+            return None;
         }
 
         // Rules should be quite simple:
@@ -692,7 +850,8 @@ impl DungeonGenerator {
         };
 
         let neighbor_locations = loc.get_neighbors();
-        let neighbor_cells: Vec<(GridLocation, MaybeCell<DungeonCellType>)> =
+        let registry = grid.get_registry();
+        let neighbor_cells: Vec<(GridLocation, MaybeCell)> =
             neighbor_locations.into_iter().map(|neighbor_loc| (neighbor_loc, grid.get_cell(&neighbor_loc))).collect();
 
         let mut neighbor_check_index = 0;
@@ -708,16 +867,17 @@ impl DungeonGenerator {
             let result = match maybe_cell {
                 None => RequirementStatus::Banned,
                 Some(cell) => {
-                    let other_types = &(*cell.borrow()).possible_types;
-                    if other_types.len() > 1 {
+                    let other_types = &cell.possible_types;
+                    let other_type_count = other_types.count();
+                    if other_type_count > 1 {
                         RequirementStatus::Neutral
-                    } else if other_types.len() == 0 {
+                    } else if other_type_count == 0 {
                         RequirementStatus::Banned
                     } else {
-                        match other_types[0] {
+                        match registry.get(other_types.first().unwrap()) {
                             DungeonCellType::None => RequirementStatus::Banned,
-                            DungeonCellType::Hall(connections) => if cell_connects(neighbor_check_index, &connections) { RequirementStatus::Required } else { RequirementStatus::Banned },
-                            DungeonCellType::Room(connections) => if cell_connects(neighbor_check_index, &connections) { RequirementStatus::Required } else { RequirementStatus::Banned },
+                            DungeonCellType::Hall(connections) => if cell_connects(neighbor_check_index, connections) { RequirementStatus::Required } else { RequirementStatus::Banned },
+                            DungeonCellType::Room(connections) => if cell_connects(neighbor_check_index, connections) { RequirementStatus::Required } else { RequirementStatus::Banned },
                         }
                     }
                 },
@@ -728,37 +888,570 @@ impl DungeonGenerator {
             result
         }).collect();
 
-        let require_connections = |cons: Vec<bool>| {
-            let mut index = 0;
+        if required_connections.iter().all(|value| *value == RequirementStatus::Banned) {
+            possible_types = registry.set_of(&[DungeonCellType::None]);
+        } else {
+            // Required directions keep only the types connecting that way, and banned ones drop them. None never connects, so any
+            // requirement rules it out.
+            for (direction, requirement) in required_connections.iter().enumerate() {
+                match requirement {
+                    RequirementStatus::Required => possible_types.intersect_with(&CONNECTING_TYPES[direction]),
+                    RequirementStatus::Banned => possible_types.subtract(&CONNECTING_TYPES[direction]),
+                    RequirementStatus::Neutral => {},
+                }
+            }
+        }
 
-            cons.iter().all(|val| {
-                let requirement = required_connections[index];
-                index += 1;
+        if possible_types.count() < initial_option_count { Some(possible_types) } else { None }
+    }
+}
 
-                if requirement == RequirementStatus::Required {
-                    *val
-                } else if requirement == RequirementStatus::Banned {
-                    !*val
-                } else {
-                    true
-                }
-            })
+/// Places the goal room and the start cell
+#[derive(Debug, Clone)]
+pub struct PlaceRoomsStage {}
+
+impl GenerationStage for PlaceRoomsStage {
+    fn get_name(&self) -> &str {
+        "Placing Rooms"
+    }
+
+    fn step(&mut self, generator: &mut DungeonGenerator) -> Result<StageStatus, DungeonError> {
+        #[cfg(target_arch = "wasm32")]
+        log!("DGEN: INIT");
+
+        generator.goal_location = generator.random_interior_location();
+
+        generator.goal_entrance_location = generator.goal_location.get_neighbors()[0];
+
+        let goal_in_small_room = generator.room_random.chance(generator.config.goal_in_small_room_chance);
+        let mut goal_connections = CellConnections::new(true, false, false, false, false, false);
+
+        let goal_locations = if goal_in_small_room {
+            generator.add_room(&DungeonRoom { shape: DungeonRoomShape::Single(goal_connections), location: generator.goal_location, doors: vec![] })?
+        } else {
+            goal_connections.bottom_left = true;
+            goal_connections.bottom_right = true;
+            generator.add_room(&DungeonRoom { shape: DungeonRoomShape::Cluster3(
+                goal_connections,
+                CellConnections::new(false, true, true, false, false, false),
+                CellConnections::new(true, false, false, false, false, true),
+            ), location: generator.goal_location, doors: vec![] })?
         };
+        generator.goal_locations = goal_locations;
 
-        let must_connect_to_something = required_connections.iter().any(|value| *value == RequirementStatus::Required);
+        generator.start_location = generator.random_interior_location();
 
-        if required_connections.iter().all(|value| *value == RequirementStatus::Banned) {
-            cell.possible_types = vec![DungeonCellType::None];
+        // Starting on the goal entrance would leave the path nowhere to go
+        while generator.goal_locations.contains(&generator.start_location) || generator.start_location == generator.goal_entrance_location {
+            generator.start_location = generator.random_interior_location();
+        }
+
+        let start_in_hall = generator.room_random.chance(generator.config.start_in_hall_chance);
+
+        if start_in_hall {
+            let start_connections = generator.random_connections();
+            generator.wfc.apply_fixed_types(vec![(
+                generator.start_location,
+                vec![DungeonCellType::Hall(start_connections)],
+            )]);
+        } else {
+            // TODO: Don't try to connect to edge
+            let mut start_connections = CellConnections::none().to_vec();
+            start_connections[generator.room_random.index_below(6)] = true;
+            generator.add_room(&DungeonRoom { shape: DungeonRoomShape::Single(CellConnections::from_vec(start_connections)), location: generator.start_location, doors: vec![] })?;
+        }
+
+        Ok(StageStatus::Done)
+    }
+
+    fn clone_stage(&self) -> Box<dyn GenerationStage> {
+        Box::new(self.clone())
+    }
+}
+
+/// Scatters the config's extra rooms around the dungeon, trying to place one room each step
+#[derive(Debug, Clone)]
+pub struct ScatterRoomsStage {
+    /// How many rooms have been tried so far, whether they fit or not
+    attempted_count: usize,
+}
+
+impl GenerationStage for ScatterRoomsStage {
+    fn get_name(&self) -> &str {
+        "Scattering Rooms"
+    }
+
+    fn step(&mut self, generator: &mut DungeonGenerator) -> Result<StageStatus, DungeonError> {
+        if self.attempted_count >= generator.config.room_count {
+            return Ok(StageStatus::Done);
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        log!("DGEN: SCATTERING_ROOMS");
+        self.attempted_count += 1;
+
+        for _ in 0..generator.config.room_placement_attempts {
+            let kind = RoomKind::pick(&generator.config.room_kind_weights, &mut generator.room_random);
+            let shape = kind.random_shape(&mut generator.room_random);
+            let location = generator.random_interior_location();
+
+            if generator.can_place_room(&shape, location) {
+                let doors = generator.choose_doors(&shape, location, 1);
+                let room = DungeonRoom { shape, location, doors };
+                generator.add_room(&room)?;
+                generator.rooms.push(room);
+                break;
+            }
+        }
+
+        if self.attempted_count >= generator.config.room_count { Ok(StageStatus::Done) } else { Ok(StageStatus::Working) }
+    }
+
+    fn get_progress(&self, generator: &DungeonGenerator) -> f64 {
+        if generator.config.room_count == 0 { 1.0 } else { self.attempted_count as f64 / generator.config.room_count as f64 }
+    }
+
+    fn clone_stage(&self) -> Box<dyn GenerationStage> {
+        Box::new(self.clone())
+    }
+}
+
+/// Walks halls one cell per step. The first leg goes from the start to the goal entrance, then each extra room gets a leg
+/// from its door to the nearest cell of the path so far.
+#[derive(Debug, Clone)]
+pub struct ForgePathStage {
+    /// Where the current leg has gotten to. This is None until the leg's first step, which starts it where the leg begins.
+    cursor_location: Option<GridLocation>,
+    /// Every cell a path has been forged through, not counting where the legs began
+    path: Vec<GridLocation>,
+    /// 0 for the path from the start to the goal, then 1 more than the index of the room being joined
+    leg: usize,
+    /// Where in `path` the current leg's cells start. Cells before this are what the leg is trying to join.
+    leg_start: usize,
+    /// How many cells the current leg has moved through, so one that can't find its way gives up
+    leg_steps: usize,
+}
+
+impl ForgePathStage {
+    /// Where the current leg begins, and the way it has to leave. The start cell's way out is worked out from its type.
+    fn get_leg_start(&self, generator: &DungeonGenerator) -> (GridLocation, Option<usize>) {
+        if self.leg == 0 {
+            (generator.start_location, None)
         } else {
-            cell.possible_types = cell.possible_types.clone().into_iter().filter(|cell_type| {
+            let room = &generator.rooms[self.leg - 1];
+            let door = room.doors[0];
+            ((CubeCoord::from_location(&room.location) + door.offset).to_location(), Some(door.direction))
+        }
+    }
+
+    /// What the current leg heads for: the goal entrance, or the cell of the path so far that's closest to the room
+    fn get_leg_target(&self, generator: &DungeonGenerator) -> GridLocation {
+        if self.leg == 0 {
+            return generator.goal_entrance_location;
+        }
+
+        let leg_start = CubeCoord::from_location(&self.get_leg_start(generator).0);
+        *self.path[..self.leg_start].iter().min_by_key(|location| CubeCoord::from_location(location).distance(&leg_start)).unwrap()
+    }
+
+    /// Moves on to the next room's leg, or finishes once every room has one
+    fn finish_leg(&mut self, generator: &DungeonGenerator) -> StageStatus {
+        self.leg += 1;
+        self.leg_start = self.path.len();
+        self.leg_steps = 0;
+        self.cursor_location = None;
+
+        if self.leg > generator.rooms.len() { StageStatus::Done } else { StageStatus::Working }
+    }
+
+    /// Whether the path may go through the location: on the grid, and not the start or part of a room
+    fn is_open(generator: &DungeonGenerator, location: &GridLocation) -> bool {
+        generator.wfc.get_grid().get_cell(location).is_some()
+            && *location != generator.start_location
+            && !generator.goal_locations.contains(location)
+            && !generator.is_room_location(location)
+    }
+}
+
+impl GenerationStage for ForgePathStage {
+    fn get_name(&self) -> &str {
+        "Forging Path"
+    }
+
+    fn step(&mut self, generator: &mut DungeonGenerator) -> Result<StageStatus, DungeonError> {
+        #[cfg(target_arch = "wasm32")]
+        log!("DGEN: FORGING_PATH");
+
+        generator.check_start_placed()?;
+        let (leg_start_location, leg_start_direction) = self.get_leg_start(generator);
+        let cursor_location = *self.cursor_location.get_or_insert(leg_start_location);
+        let neighbor_locations = cursor_location.get_neighbors();
+
+        let next_location_index = if cursor_location == leg_start_location {
+            let direction = match leg_start_direction {
+                Some(direction) => direction,
+                None => {
+                    let start_cell = generator.wfc.get_grid().get_cell(&generator.start_location).unwrap();
+                    let start_type = start_cell.possible_types.first().map(|index| *generator.wfc.get_grid().get_registry().get(index));
+                    let start_connections = match start_type {
+                        Some(DungeonCellType::Hall(cons)) => cons,
+                        Some(DungeonCellType::Room(cons)) => cons,
+                        _ => return Err(DungeonError::StartNotConnecting(generator.start_location)),
+                    }.to_vec();
+
+                    match start_connections.iter().position(|i| *i) {
+                        Some(index) => index,
+                        None => return Err(DungeonError::StartNotConnecting(generator.start_location)),
+                    }
+                },
+            };
+
+            // The way out of where the leg begins is always the same, so this would never get anywhere
+            if !ForgePathStage::is_open(generator, &neighbor_locations[direction]) {
+                return Err(DungeonError::PathBlocked(leg_start_location));
+            }
+
+            direction
+        } else {
+            // Blocked neighbors are left out up front, so the path always moves rather than picking one over and over
+            let open_directions: Vec<usize> = (0..neighbor_locations.len())
+                .filter(|direction| ForgePathStage::is_open(generator, &neighbor_locations[*direction]))
+                .collect();
+
+            let max_steps = MAX_PATH_STEPS_PER_CELL * generator.config.rows * generator.config.cols;
+            if open_directions.is_empty() || self.leg_steps >= max_steps {
+                return Err(DungeonError::PathStuck(leg_start_location));
+            }
+
+            let target = CubeCoord::from_location(&self.get_leg_target(generator));
+            let choose_closest = generator.path_random.chance(generator.config.meander_factor);
+            if choose_closest {
+                *open_directions.iter().min_by_key(|direction| CubeCoord::from_location(&neighbor_locations[**direction]).distance(&target)).unwrap()
+            } else {
+                open_directions[generator.path_random.index_below(open_directions.len())]
+            }
+        };
+
+        let next_location = neighbor_locations[next_location_index];
+        self.leg_steps += 1;
+
+        if let Some(next_cell) = generator.wfc.get_grid().get_cell(&next_location) {
+            let mut next_required_connections = CellConnections::none().to_vec();
+            next_required_connections[CellConnections::opposite_index(next_location_index)] = true;
+            if next_location == generator.goal_entrance_location {
+                // Force connection to goal. For now, the goal entrance is always top left
+                next_required_connections[CellConnections::BOTTOM_RIGHT] = true;
+            }
+
+            let next_cell_types = generator.wfc.get_grid().cell_types(next_cell).into_iter().filter(|cell_type| {
                 match cell_type {
-                    DungeonCellType::None => !must_connect_to_something,
-                    DungeonCellType::Hall(connections) => require_connections(connections.to_vec()),
-                    DungeonCellType::Room(connections) => require_connections(connections.to_vec()),
+                    DungeonCellType::None => false,
+                    DungeonCellType::Room(_) => false,
+                    DungeonCellType::Hall(connections) => {
+                        let connections_vec = connections.to_vec();
+                        for i in 0..connections_vec.len() {
+                            if next_required_connections[i] && !connections_vec[i] {
+                                return false;
+                            }
+                        }
+
+                        true
+                    },
                 }
             }).collect();
+
+            generator.wfc.apply_fixed_types(vec![(
+                next_location,
+                next_cell_types,
+            )]);
+
+            // A leg's first cell is joined to where it began by the start's or the door's own connection
+            if self.path.len() > self.leg_start {
+                // make sure the previous cell connects here
+                let previous_location = *self.path.last().unwrap();
+                let previous_cell = generator.wfc.get_grid().get_cell(&previous_location).unwrap();
+                let mut previous_required_connections = CellConnections::none().to_vec();
+                previous_required_connections[next_location_index] = true;
+
+                let previous_cell_types: Vec<DungeonCellType> = generator.wfc.get_grid().cell_types(previous_cell).into_iter().filter(|cell_type| {
+                    match cell_type {
+                        DungeonCellType::None => false,
+                        DungeonCellType::Room(_) => false,
+                        DungeonCellType::Hall(connections) => {
+                            let connections_vec = connections.to_vec();
+                            for i in 0..connections_vec.len() {
+                                if previous_required_connections[i] && !connections_vec[i] {
+                                    return false;
+                                }
+                            }
+
+                            true
+                        },
+                    }
+                }).collect();
+
+                generator.wfc.apply_fixed_types(vec![(
+                    previous_location,
+                    previous_cell_types,
+                )]);
+            }
+
+            // A room's leg is done as soon as it reaches the path that's already there
+            if self.path[..self.leg_start].contains(&next_location) {
+                return Ok(self.finish_leg(generator));
+            }
+
+            self.path.push(next_location);
+
+            self.cursor_location = Some(next_location);
+            if self.leg == 0 && next_location == generator.goal_entrance_location {
+                return Ok(self.finish_leg(generator));
+            }
+        }
+
+        Ok(StageStatus::Working)
+    }
+
+    /// How many legs are done, plus how much closer the current leg is to its target than where it began. Meandering away
+    /// can make this go back down.
+    fn get_progress(&self, generator: &DungeonGenerator) -> f64 {
+        let leg_count = generator.rooms.len() + 1;
+        let leg_progress = match self.cursor_location {
+            _ if self.leg >= leg_count => 0.0,
+            None => 0.0,
+            Some(cursor_location) => {
+                let target = CubeCoord::from_location(&self.get_leg_target(generator));
+                let start_distance = CubeCoord::from_location(&self.get_leg_start(generator).0).distance(&target);
+                let cursor_distance = CubeCoord::from_location(&cursor_location).distance(&target);
+
+                if start_distance == 0 { 1.0 } else { 1.0 - cursor_distance as f64 / start_distance as f64 }
+            },
+        };
+
+        (self.leg as f64 + leg_progress) / leg_count as f64
+    }
+
+    fn clone_stage(&self) -> Box<dyn GenerationStage> {
+        Box::new(self.clone())
+    }
+}
+
+/// Collapses one of the remaining open cells to a random type each step, until every cell is settled
+#[derive(Debug, Clone)]
+pub struct FillStage {}
+
+impl GenerationStage for FillStage {
+    fn get_name(&self) -> &str {
+        "Fill"
+    }
+
+    fn step(&mut self, generator: &mut DungeonGenerator) -> Result<StageStatus, DungeonError> {
+        #[cfg(target_arch = "wasm32")]
+        log!("DGEN: FILL");
+
+        let fill_random = &mut generator.fill_random;
+        let config = &generator.config;
+        let next_location = generator.wfc.select_cell(
+            generator.observation_strategy,
+            &|cell_type| config.cell_weights.cell_type_weight(cell_type),
+            &mut |count| fill_random.index_below(count),
+        );
+
+        match next_location {
+            None => Ok(StageStatus::Done),
+            Some(location) => {
+                generator.apply_random_cell_type(&[location]);
+                Ok(StageStatus::Settle)
+            },
+        }
+    }
+
+    /// The share of cells that are settled
+    fn get_progress(&self, generator: &DungeonGenerator) -> f64 {
+        let cells = generator.wfc.get_grid().cells();
+        cells.iter().filter(|cell| cell.is_settled()).count() as f64 / cells.len() as f64
+    }
+
+    fn get_steps_per_frame(&self) -> usize {
+        20
+    }
+
+    fn clone_stage(&self) -> Box<dyn GenerationStage> {
+        Box::new(self.clone())
+    }
+}
+
+/// Empties every cell that can't be reached from the start
+#[derive(Debug, Clone)]
+pub struct CleanStage {}
+
+impl GenerationStage for CleanStage {
+    fn get_name(&self) -> &str {
+        "Clean"
+    }
+
+    fn step(&mut self, generator: &mut DungeonGenerator) -> Result<StageStatus, DungeonError> {
+        generator.check_start_placed()?;
+
+        let grid = generator.wfc.get_grid();
+        let registry = grid.get_registry();
+        let empty_types = registry.none();
+        let rows = generator.config.rows;
+        let cols = generator.config.cols;
+
+        // A lookup grid rather than a list of locations, so this stays linear on big dungeons
+        let mut is_connected = vec![vec![false; cols]; rows];
+        is_connected[generator.start_location.row as usize][generator.start_location.col as usize] = true;
+
+        let mut depth_queue = VecDeque::<GridLocation>::new();
+        depth_queue.push_back(generator.start_location);
+
+        while let Some(location) = depth_queue.pop_front() {
+            let cell = grid.get_cell(&location).unwrap();
+            let connections = match cell.possible_types.first() {
+                Some(index) => registry.get(index).connections(),
+                None => CellConnections::none(),
+            }.to_vec();
+
+            for (neighbor_index, neighbor) in location.get_neighbors().into_iter().enumerate() {
+                if connections[neighbor_index] && grid.get_cell(&neighbor).is_some() && !is_connected[neighbor.row as usize][neighbor.col as usize] {
+                    is_connected[neighbor.row as usize][neighbor.col as usize] = true;
+                    depth_queue.push_back(neighbor);
+                }
+            }
+        }
+
+        for (row, row_connected) in is_connected.iter().enumerate() {
+            for (col, connected) in row_connected.iter().enumerate() {
+                // If it doesn't connect to anything we can get to, remove the cell
+                if !connected {
+                    generator.wfc.set_cell_types(&GridLocation::new(row as i64, col as i64), empty_types.clone());
+                }
+            }
+        }
+
+        // Cleaning edits cells directly, so nothing before this point can be safely rolled back
+        generator.wfc.clear_decisions();
+
+        Ok(StageStatus::Done)
+    }
+
+    fn clone_stage(&self) -> Box<dyn GenerationStage> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generated_hash(config: DungeonGeneratorConfig) -> u64 {
+        let mut generator = DungeonGenerator::new(config).unwrap();
+        generator.generate().unwrap();
+        generator.wfc.get_grid().content_hash()
+    }
+
+    #[test]
+    fn the_same_seed_generates_the_same_dungeon() {
+        let config = DungeonGeneratorConfig { seed: 42, room_count: 3, generation_attempts: 5, ..Default::default() };
+
+        assert_eq!(generated_hash(config.clone()), generated_hash(config));
+    }
+
+    #[test]
+    fn a_fixed_seed_generates_a_known_dungeon() {
+        let config = DungeonGeneratorConfig { seed: 7, room_count: 3, generation_attempts: 5, ..Default::default() };
+
+        assert_eq!(generated_hash(config), 18312964138131780023);
+    }
+
+    #[test]
+    fn a_restored_generator_finishes_the_same_way_again() {
+        let mut generator = DungeonGenerator::new(DungeonGeneratorConfig { seed: 42, room_count: 3, ..Default::default() }).unwrap();
+        for _ in 0..20 {
+            generator.step().unwrap();
         }
+        let snapshot = generator.snapshot();
 
-        cell.possible_types.len() < initial_option_count
+        let first = generator.generate().map(|_| generator.wfc.get_grid().content_hash());
+        generator.restore(&snapshot);
+        let second = generator.generate().map(|_| generator.wfc.get_grid().content_hash());
+
+        assert!(first.is_ok());
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn the_first_attempt_uses_the_seed_as_it_is() {
+        assert_eq!(DungeonGenerator::attempt_seed(42, 0), 42);
+        assert_ne!(DungeonGenerator::attempt_seed(42, 1), 42);
+        assert_ne!(DungeonGenerator::attempt_seed(42, 1), DungeonGenerator::attempt_seed(42, 2));
+    }
+
+    #[test]
+    fn a_retried_attempt_can_be_reproduced_from_the_seed_and_its_number() {
+        let config = DungeonGeneratorConfig { seed: 17, generation_attempts: 5, ..Default::default() };
+        let mut retried = DungeonGenerator::new(config.clone()).unwrap();
+        retried.generate().unwrap();
+        assert!(retried.get_attempt() > 0);
+
+        let mut reproduced = DungeonGenerator::new(config).unwrap();
+        reproduced.start_attempt(retried.get_attempt());
+        reproduced.generate().unwrap();
+
+        assert_eq!(reproduced.get_attempt_seed(), retried.get_attempt_seed());
+        assert_eq!(reproduced.wfc.get_grid().content_hash(), retried.wfc.get_grid().content_hash());
+    }
+
+    #[test]
+    fn forging_without_a_placed_start_is_an_error() {
+        let stages = DungeonGenerator::default_stages().into_iter().skip(1).collect();
+        let mut generator = DungeonGenerator::with_stages(DungeonGeneratorConfig::default(), stages).unwrap();
+
+        assert_eq!(generator.generate(), Err(DungeonError::NoStart));
+    }
+
+    #[test]
+    fn cleaning_without_a_placed_start_is_an_error() {
+        let stages = DungeonGenerator::default_stages().into_iter().skip(3).collect();
+        let mut generator = DungeonGenerator::with_stages(DungeonGeneratorConfig::default(), stages).unwrap();
+
+        assert_eq!(generator.generate(), Err(DungeonError::NoStart));
+    }
+
+    #[test]
+    fn scattered_rooms_keep_their_spacing_and_get_a_path() {
+        let config = DungeonGeneratorConfig { seed: 3, room_count: 4, generation_attempts: 5, ..Default::default() };
+        let mut generator = DungeonGenerator::new(config.clone()).unwrap();
+        generator.generate().unwrap();
+        assert!(generator.rooms.len() >= 2, "Only {} rooms fit", generator.rooms.len());
+
+        let mut taken_locations = generator.goal_locations.clone();
+        taken_locations.push(generator.goal_entrance_location);
+        taken_locations.push(generator.start_location);
+
+        for room in generator.rooms.iter() {
+            let locations = room.get_locations();
+            for location in locations.iter() {
+                assert!(location.row >= 1 && location.row < config.rows as i64 - 1, "{:?} is in the margin", location);
+                assert!(location.col >= 1 && location.col < config.cols as i64 - 1, "{:?} is in the margin", location);
+
+                let coord = CubeCoord::from_location(location);
+                for taken in taken_locations.iter() {
+                    assert!(coord.distance(&CubeCoord::from_location(taken)) >= config.room_spacing as i64, "{:?} is too close to {:?}", location, taken);
+                }
+            }
+
+            // The leg out of the room starts with a hall just past its door, connecting back in
+            let door = room.doors[0];
+            let outside_location = (CubeCoord::from_location(&room.location) + door.get_outside_offset()).to_location();
+            let grid = generator.wfc.get_grid();
+            match grid.cell_types(grid.get_cell(&outside_location).unwrap()).as_slice() {
+                [DungeonCellType::Hall(connections)] => assert!(connections.to_vec()[CellConnections::opposite_index(door.direction)]),
+                other => panic!("Expected a hall outside the door at {:?}, found {:?}", outside_location, other),
+            }
+
+            taken_locations.extend(locations);
+        }
     }
 }
@@ -0,0 +1,371 @@
+use crate::wfc::*;
+use crate::generation_fields::dungeon::*;
+use crate::generation_fields::cell_probability::*;
+
+/// The first line of a saved config
+const CONFIG_HEADER: &str = "dungeoroo-config 1";
+
+/// The smallest grid a dungeon fits on. The goal and start are kept off the edges, and need room to not overlap.
+pub const MIN_DUNGEON_SIZE: usize = 5;
+
+/// The closest rooms can be. Any closer and a room's door could open straight into another room.
+pub const MIN_ROOM_SPACING: usize = 2;
+
+/// Everything that shapes a generated dungeon
+#[derive(Debug, PartialEq, Clone)]
+pub struct DungeonGeneratorConfig {
+    pub seed: u64,
+    pub rows: usize,
+    pub cols: usize,
+    /// How likely each step of the path is to head straight for the goal rather than wander, from 0 to 1
+    pub meander_factor: f64,
+    /// How the Fill state picks the next cell to collapse, to begin with
+    pub observation_strategy: ObservationStrategy,
+    /// How many decisions the generator can roll back when it hits a contradiction
+    pub backtrack_limit: usize,
+    /// How many times generate can start over with a new seed when an attempt fails. Above 1, finished dungeons are
+    /// validated, and problems count as failures.
+    pub generation_attempts: usize,
+    /// How likely the goal is a single cell rather than a cluster of three, from 0 to 1
+    pub goal_in_small_room_chance: f64,
+    /// How likely the start is a hall rather than a single-cell room, from 0 to 1
+    pub start_in_hall_chance: f64,
+    /// How likely a hall start connects in each direction, from 0 to 1
+    pub start_connection_chance: f64,
+    /// How many extra rooms to scatter around the dungeon, besides the start and goal
+    pub room_count: usize,
+    /// The fewest steps allowed between cells of different rooms. The start and goal count as rooms.
+    pub room_spacing: usize,
+    /// How many spots to try for each extra room before giving up on it
+    pub room_placement_attempts: usize,
+    /// How likely each kind of extra room is, in RoomKind::ALL order
+    pub room_kind_weights: [f64; 5],
+    /// How likely filling picks each cell type
+    pub cell_weights: CellProbabilityModel,
+}
+
+impl Default for DungeonGeneratorConfig {
+    fn default() -> Self {
+        DungeonGeneratorConfig {
+            seed: 1,
+            rows: 15,
+            cols: 20,
+            meander_factor: 0.7,
+            observation_strategy: ObservationStrategy::MinEntropy,
+            backtrack_limit: DEFAULT_BACKTRACK_LIMIT,
+            generation_attempts: 1,
+            goal_in_small_room_chance: 0.5,
+            start_in_hall_chance: 0.5,
+            start_connection_chance: 0.5,
+            room_count: 0,
+            room_spacing: 3,
+            room_placement_attempts: 20,
+            room_kind_weights: [1.0, 1.0, 1.0, 1.0, 0.5],
+            cell_weights: CellProbabilityModel::default(),
+        }
+    }
+}
+
+impl DungeonGeneratorConfig {
+    /// Checks every setting is in range, returning a message about the first one that isn't
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rows < MIN_DUNGEON_SIZE || self.cols < MIN_DUNGEON_SIZE {
+            return Err(format!("Dungeons must be at least {}x{}", MIN_DUNGEON_SIZE, MIN_DUNGEON_SIZE));
+        }
+
+        if self.generation_attempts < 1 {
+            return Err(format!("{} must be at least 1", DungeonSetting::GenerationAttempts.get_label()));
+        }
+
+        for (setting, value) in [
+            (DungeonSetting::MeanderFactor, self.meander_factor),
+            (DungeonSetting::GoalInSmallRoomChance, self.goal_in_small_room_chance),
+            (DungeonSetting::StartInHallChance, self.start_in_hall_chance),
+            (DungeonSetting::StartConnectionChance, self.start_connection_chance),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("{} must be between 0 and 1", setting.get_label()));
+            }
+        }
+
+        if self.room_spacing < MIN_ROOM_SPACING {
+            return Err(format!("{} must be at least {}, so paths fit between rooms", DungeonSetting::RoomSpacing.get_label(), MIN_ROOM_SPACING));
+        }
+
+        if self.room_kind_weights.iter().any(|weight| !weight.is_finite() || *weight < 0.0) {
+            return Err(String::from("Room kind weights can't be negative"));
+        }
+
+        if self.room_count > 0 && self.room_kind_weights.iter().sum::<f64>() <= 0.0 {
+            return Err(String::from("At least one room kind weight has to be above 0"));
+        }
+
+        self.cell_weights.validate()
+    }
+
+    /// Gets a setting as it's written in text
+    pub fn get_value(&self, setting: DungeonSetting) -> String {
+        match setting {
+            DungeonSetting::Seed => self.seed.to_string(),
+            DungeonSetting::Rows => self.rows.to_string(),
+            DungeonSetting::Cols => self.cols.to_string(),
+            DungeonSetting::MeanderFactor => self.meander_factor.to_string(),
+            DungeonSetting::ObservationStrategy => strategy_to_text(self.observation_strategy).to_string(),
+            DungeonSetting::BacktrackLimit => self.backtrack_limit.to_string(),
+            DungeonSetting::GenerationAttempts => self.generation_attempts.to_string(),
+            DungeonSetting::GoalInSmallRoomChance => self.goal_in_small_room_chance.to_string(),
+            DungeonSetting::StartInHallChance => self.start_in_hall_chance.to_string(),
+            DungeonSetting::StartConnectionChance => self.start_connection_chance.to_string(),
+            DungeonSetting::RoomCount => self.room_count.to_string(),
+            DungeonSetting::RoomSpacing => self.room_spacing.to_string(),
+            DungeonSetting::RoomPlacementAttempts => self.room_placement_attempts.to_string(),
+            DungeonSetting::RoomKindWeights => CellProbabilityModel::weights_to_text(&self.room_kind_weights),
+            DungeonSetting::NoneWeight => self.cell_weights.none.to_string(),
+            DungeonSetting::HallWeights => CellProbabilityModel::weights_to_text(&self.cell_weights.halls),
+            DungeonSetting::RoomWeights => CellProbabilityModel::weights_to_text(&self.cell_weights.rooms),
+            DungeonSetting::NeighborWeights => match &self.cell_weights.neighbor_weights {
+                None => String::from("none"),
+                Some(neighbor_weights) => neighbor_weights.to_text(),
+            },
+        }
+    }
+
+    /// Sets a setting from text. This only checks the text parses; use validate to check the value makes sense.
+    pub fn set_value(&mut self, setting: DungeonSetting, value: &str) -> Result<(), String> {
+        let bad_value = || format!("\"{}\" isn't valid for {}", value, setting.get_label().to_lowercase());
+        let whole_number = || value.parse::<usize>().map_err(|_| bad_value());
+        let number = || value.parse::<f64>().map_err(|_| bad_value());
+        let weights = || CellProbabilityModel::weights_from_text(value).ok_or_else(bad_value);
+
+        match setting {
+            DungeonSetting::Seed => self.seed = value.parse::<u64>().map_err(|_| bad_value())?,
+            DungeonSetting::Rows => self.rows = whole_number()?,
+            DungeonSetting::Cols => self.cols = whole_number()?,
+            DungeonSetting::MeanderFactor => self.meander_factor = number()?,
+            DungeonSetting::ObservationStrategy => self.observation_strategy = strategy_from_text(value).ok_or_else(bad_value)?,
+            DungeonSetting::BacktrackLimit => self.backtrack_limit = whole_number()?,
+            DungeonSetting::GenerationAttempts => self.generation_attempts = whole_number()?,
+            DungeonSetting::GoalInSmallRoomChance => self.goal_in_small_room_chance = number()?,
+            DungeonSetting::StartInHallChance => self.start_in_hall_chance = number()?,
+            DungeonSetting::StartConnectionChance => self.start_connection_chance = number()?,
+            DungeonSetting::RoomCount => self.room_count = whole_number()?,
+            DungeonSetting::RoomSpacing => self.room_spacing = whole_number()?,
+            DungeonSetting::RoomPlacementAttempts => self.room_placement_attempts = whole_number()?,
+            DungeonSetting::RoomKindWeights => {
+                self.room_kind_weights = parse_weights(value).and_then(|weights| weights.try_into().ok()).ok_or_else(bad_value)?;
+            },
+            DungeonSetting::NoneWeight => self.cell_weights.none = number()?,
+            DungeonSetting::HallWeights => self.cell_weights.halls = weights()?,
+            DungeonSetting::RoomWeights => self.cell_weights.rooms = weights()?,
+            DungeonSetting::NeighborWeights => {
+                self.cell_weights.neighbor_weights = if value == "none" { None } else { Some(NeighborWeights::from_text(value).ok_or_else(bad_value)?) };
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Each setting as a "keyword value" line, in DungeonSetting::ALL order
+    pub fn to_lines(&self) -> Vec<String> {
+        DungeonSetting::ALL.iter().map(|setting| format!("{} {}", setting.get_keyword(), self.get_value(*setting))).collect()
+    }
+
+    /// Writes the config as plain text, one setting per line
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![CONFIG_HEADER.to_string()];
+        lines.append(&mut self.to_lines());
+
+        lines.join("\n")
+    }
+
+    /// Reads a config written by to_text. Settings that are left out keep their defaults, and the result is validated.
+    pub fn from_text(text: &str) -> Result<DungeonGeneratorConfig, String> {
+        let mut lines = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).enumerate();
+
+        match lines.next() {
+            Some((_, CONFIG_HEADER)) => {},
+            _ => return Err(format!("Configs must start with \"{}\"", CONFIG_HEADER)),
+        }
+
+        let mut config = DungeonGeneratorConfig::default();
+        for (index, line) in lines {
+            let (keyword, value) = line.split_once(' ').ok_or_else(|| format!("Line {}: Expected a keyword and a value", index + 1))?;
+            let setting = DungeonSetting::from_keyword(keyword).ok_or_else(|| format!("Line {}: Unknown setting {}", index + 1, keyword))?;
+            config.set_value(setting, value).map_err(|message| format!("Line {}: {}", index + 1, message))?;
+        }
+
+        config.validate()?;
+
+        Ok(config)
+    }
+}
+
+/// Names each field of DungeonGeneratorConfig, so settings can be read, written and edited one at a time
+#[derive(Debug, Eq, Copy, Clone, PartialEq, Hash)]
+pub enum DungeonSetting {
+    Seed,
+    Rows,
+    Cols,
+    MeanderFactor,
+    ObservationStrategy,
+    BacktrackLimit,
+    GenerationAttempts,
+    GoalInSmallRoomChance,
+    StartInHallChance,
+    StartConnectionChance,
+    RoomCount,
+    RoomSpacing,
+    RoomPlacementAttempts,
+    RoomKindWeights,
+    NoneWeight,
+    HallWeights,
+    RoomWeights,
+    NeighborWeights,
+}
+
+impl DungeonSetting {
+    pub const ALL: [DungeonSetting; 18] = [
+        DungeonSetting::Seed,
+        DungeonSetting::Rows,
+        DungeonSetting::Cols,
+        DungeonSetting::MeanderFactor,
+        DungeonSetting::ObservationStrategy,
+        DungeonSetting::BacktrackLimit,
+        DungeonSetting::GenerationAttempts,
+        DungeonSetting::GoalInSmallRoomChance,
+        DungeonSetting::StartInHallChance,
+        DungeonSetting::StartConnectionChance,
+        DungeonSetting::RoomCount,
+        DungeonSetting::RoomSpacing,
+        DungeonSetting::RoomPlacementAttempts,
+        DungeonSetting::RoomKindWeights,
+        DungeonSetting::NoneWeight,
+        DungeonSetting::HallWeights,
+        DungeonSetting::RoomWeights,
+        DungeonSetting::NeighborWeights,
+    ];
+
+    /// The setting's name in saved text
+    pub fn get_keyword(&self) -> &'static str {
+        match self {
+            DungeonSetting::Seed => "seed",
+            DungeonSetting::Rows => "rows",
+            DungeonSetting::Cols => "cols",
+            DungeonSetting::MeanderFactor => "meander",
+            DungeonSetting::ObservationStrategy => "strategy",
+            DungeonSetting::BacktrackLimit => "backtrack",
+            DungeonSetting::GenerationAttempts => "attempts",
+            DungeonSetting::GoalInSmallRoomChance => "goal-small-room-chance",
+            DungeonSetting::StartInHallChance => "start-hall-chance",
+            DungeonSetting::StartConnectionChance => "start-connection-chance",
+            DungeonSetting::RoomCount => "room-count",
+            DungeonSetting::RoomSpacing => "room-spacing",
+            DungeonSetting::RoomPlacementAttempts => "room-attempts",
+            DungeonSetting::RoomKindWeights => "room-kind-weights",
+            DungeonSetting::NoneWeight => "none-weight",
+            DungeonSetting::HallWeights => "hall-weights",
+            DungeonSetting::RoomWeights => "room-weights",
+            DungeonSetting::NeighborWeights => "neighbor-weights",
+        }
+    }
+
+    /// The setting's name in the UI
+    pub fn get_label(&self) -> &'static str {
+        match self {
+            DungeonSetting::Seed => "Seed",
+            DungeonSetting::Rows => "Rows",
+            DungeonSetting::Cols => "Columns",
+            DungeonSetting::MeanderFactor => "Meander factor",
+            DungeonSetting::ObservationStrategy => "Fill strategy",
+            DungeonSetting::BacktrackLimit => "Backtrack limit",
+            DungeonSetting::GenerationAttempts => "Generation attempts",
+            DungeonSetting::GoalInSmallRoomChance => "Small goal room chance",
+            DungeonSetting::StartInHallChance => "Start in hall chance",
+            DungeonSetting::StartConnectionChance => "Start connection chance",
+            DungeonSetting::RoomCount => "Extra rooms",
+            DungeonSetting::RoomSpacing => "Room spacing",
+            DungeonSetting::RoomPlacementAttempts => "Room placement attempts",
+            DungeonSetting::RoomKindWeights => "Room kind weights",
+            DungeonSetting::NoneWeight => "None weight",
+            DungeonSetting::HallWeights => "Hall weights",
+            DungeonSetting::RoomWeights => "Room weights",
+            DungeonSetting::NeighborWeights => "Neighbor weights",
+        }
+    }
+
+    /// Whether the setting is one of the cell weights, which are edited as a CellProbabilityModel rather than as text
+    pub fn is_cell_weight(&self) -> bool {
+        matches!(self, DungeonSetting::NoneWeight | DungeonSetting::HallWeights | DungeonSetting::RoomWeights | DungeonSetting::NeighborWeights)
+    }
+
+    pub fn from_keyword(keyword: &str) -> Option<DungeonSetting> {
+        DungeonSetting::ALL.into_iter().find(|setting| setting.get_keyword() == keyword)
+    }
+}
+
+pub fn strategy_to_text(strategy: ObservationStrategy) -> &'static str {
+    match strategy {
+        ObservationStrategy::Random => "Random",
+        ObservationStrategy::MinEntropy => "MinEntropy",
+    }
+}
+
+pub fn strategy_from_text(text: &str) -> Option<ObservationStrategy> {
+    match text {
+        "Random" => Some(ObservationStrategy::Random),
+        "MinEntropy" => Some(ObservationStrategy::MinEntropy),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_config_reads_back_the_same_as_it_was_written() {
+        let mut neighbor_weights = NeighborWeights::new();
+        neighbor_weights.set_ratio(CellCategory::all()[0], CellCategory::all()[1], 2.5);
+
+        let config = DungeonGeneratorConfig {
+            seed: 12345,
+            meander_factor: 0.25,
+            observation_strategy: ObservationStrategy::Random,
+            generation_attempts: 4,
+            room_count: 3,
+            room_kind_weights: [0.5, 2.0, 0.0, 1.25, 3.0],
+            cell_weights: CellProbabilityModel {
+                none: 0.75,
+                halls: [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0],
+                rooms: [7.0, 6.0, 5.0, 4.0, 3.0, 2.0, 1.5],
+                neighbor_weights: Some(neighbor_weights),
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(DungeonGeneratorConfig::from_text(&config.to_text()), Ok(config));
+    }
+
+    #[test]
+    fn unknown_settings_are_an_error() {
+        let text = format!("{}\nseed 3\nsparkle 11", CONFIG_HEADER);
+
+        assert_eq!(DungeonGeneratorConfig::from_text(&text), Err(String::from("Line 3: Unknown setting sparkle")));
+    }
+
+    #[test]
+    fn values_that_dont_parse_are_an_error() {
+        for line in ["rows many", "strategy Sideways", "room-kind-weights 1,2,3", "hall-weights 1,x,1,1,1,1,1"] {
+            let text = format!("{}\n{}", CONFIG_HEADER, line);
+
+            assert!(DungeonGeneratorConfig::from_text(&text).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn values_out_of_range_are_an_error() {
+        let text = format!("{}\nmeander 1.5", CONFIG_HEADER);
+
+        assert_eq!(DungeonGeneratorConfig::from_text(&text), Err(String::from("Meander factor must be between 0 and 1")));
+    }
+}
@@ -1 +1,7 @@
+pub mod cell_probability;
 pub mod dungeon;
+pub mod dungeon_config;
+pub mod replay;
+pub mod room_shape;
+pub mod terrain;
+pub mod validator;
@@ -0,0 +1,322 @@
+use crate::wfc::*;
+use crate::generation_fields::dungeon::*;
+use crate::generation_fields::dungeon_config::*;
+
+/// The first line of every replay, so old or unrelated text is caught before anything is played
+const REPLAY_HEADER: &str = "dungeoroo-replay 2";
+
+/// Something done to a generator from outside, in the order it happened
+#[derive(Debug, PartialEq, Clone)]
+pub enum ReplayAction {
+    /// A call to DungeonGenerator::step
+    Step,
+    /// Types applied by hand, like a paint in the sandbox
+    ApplyTypes(Vec<(GridLocation, Vec<DungeonCellType>)>),
+    /// The fill strategy was switched
+    SetObservationStrategy(ObservationStrategy),
+    /// Generation started over as the given attempt
+    StartAttempt(usize),
+}
+
+/// Everything needed to rerun a dungeon's generation exactly, and to check it came out the same
+#[derive(Debug, PartialEq, Clone)]
+pub struct ReplayLog {
+    /// The settings the dungeon was generated with
+    pub config: DungeonGeneratorConfig,
+    pub actions: Vec<ReplayAction>,
+    /// The hash of the grid when generation finished, or None if it was saved part way through
+    pub final_grid_hash: Option<u64>,
+}
+
+impl ReplayLog {
+    /// Writes the log as plain text, one setting or action per line. Runs of steps are written as a single line.
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![REPLAY_HEADER.to_string()];
+        lines.append(&mut self.config.to_lines());
+
+        let mut step_count = 0;
+        for action in self.actions.iter() {
+            if *action == ReplayAction::Step {
+                step_count += 1;
+                continue;
+            }
+
+            if step_count > 0 {
+                lines.push(format!("step {}", step_count));
+                step_count = 0;
+            }
+
+            lines.push(match action {
+                ReplayAction::Step => unreachable!(),
+                ReplayAction::ApplyTypes(types) => {
+                    let cells: Vec<String> = types.iter().map(|(location, cell_types)| {
+                        let type_names: Vec<String> = cell_types.iter().map(cell_type_to_text).collect();
+                        format!("{},{}={}", location.row, location.col, type_names.join("|"))
+                    }).collect();
+
+                    format!("apply {}", cells.join(" "))
+                },
+                ReplayAction::SetObservationStrategy(strategy) => format!("set-strategy {}", strategy_to_text(*strategy)),
+                ReplayAction::StartAttempt(attempt) => format!("attempt {}", attempt),
+            });
+        }
+
+        if step_count > 0 {
+            lines.push(format!("step {}", step_count));
+        }
+
+        if let Some(hash) = self.final_grid_hash {
+            lines.push(format!("hash {}", hash));
+        }
+
+        lines.join("\n")
+    }
+
+    /// Reads a log written by to_text. Errors say which line was wrong. Settings that are left out keep their defaults.
+    pub fn from_text(text: &str) -> Result<ReplayLog, String> {
+        let mut lines = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).enumerate();
+
+        match lines.next() {
+            Some((_, REPLAY_HEADER)) => {},
+            _ => return Err(format!("Replays must start with \"{}\"", REPLAY_HEADER)),
+        }
+
+        let mut config = DungeonGeneratorConfig::default();
+        let mut actions = vec![];
+        let mut final_grid_hash = None;
+
+        for (index, line) in lines {
+            let line_error = |message: &str| format!("Line {}: {}", index + 1, message);
+            let (keyword, value) = line.split_once(' ').ok_or_else(|| line_error("Expected a keyword and a value"))?;
+
+            if let Some(setting) = DungeonSetting::from_keyword(keyword) {
+                config.set_value(setting, value).map_err(|message| line_error(&message))?;
+                continue;
+            }
+
+            match keyword {
+                "step" => {
+                    let count = value.parse::<usize>().map_err(|_| line_error("Bad step count"))?;
+                    actions.extend(std::iter::repeat_n(ReplayAction::Step, count));
+                },
+                "apply" => {
+                    let mut types = vec![];
+                    for cell in value.split_whitespace() {
+                        let (location, type_names) = cell.split_once('=').ok_or_else(|| line_error("Expected row,col=types"))?;
+                        let (row, col) = location.split_once(',').ok_or_else(|| line_error("Expected row,col"))?;
+                        let location = GridLocation::new(
+                            row.parse::<i64>().map_err(|_| line_error("Bad row"))?,
+                            col.parse::<i64>().map_err(|_| line_error("Bad column"))?,
+                        );
+
+                        let cell_types = type_names.split('|')
+                            .map(|name| cell_type_from_text(name).ok_or_else(|| line_error(&format!("Unknown cell type {}", name))))
+                            .collect::<Result<Vec<DungeonCellType>, String>>()?;
+
+                        types.push((location, cell_types));
+                    }
+
+                    actions.push(ReplayAction::ApplyTypes(types));
+                },
+                "set-strategy" => {
+                    actions.push(ReplayAction::SetObservationStrategy(strategy_from_text(value).ok_or_else(|| line_error("Unknown strategy"))?));
+                },
+                "attempt" => {
+                    actions.push(ReplayAction::StartAttempt(value.parse::<usize>().map_err(|_| line_error("Bad attempt"))?));
+                },
+                "hash" => final_grid_hash = Some(value.parse::<u64>().map_err(|_| line_error("Bad hash"))?),
+                _ => return Err(line_error(&format!("Unknown keyword {}", keyword))),
+            }
+        }
+
+        config.validate()?;
+
+        Ok(ReplayLog {
+            config,
+            actions,
+            final_grid_hash,
+        })
+    }
+}
+
+/// How a replay compared to the run it was recorded from
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ReplayVerification {
+    /// The grid came out exactly the same
+    Matched,
+    /// The grid came out different
+    Mismatched { expected: u64, actual: u64 },
+    /// The replay was saved before generation finished, so there's nothing to compare to
+    NoHash,
+    /// There are still actions left to play
+    Unfinished,
+}
+
+/// Reruns a replay log on a generator, one action at a time
+#[derive(Debug, PartialEq, Clone)]
+pub struct ReplayPlayer {
+    log: ReplayLog,
+    next_action: usize,
+}
+
+impl ReplayPlayer {
+    pub fn new(log: ReplayLog) -> ReplayPlayer {
+        ReplayPlayer {
+            log,
+            next_action: 0,
+        }
+    }
+
+    pub fn get_log(&self) -> &ReplayLog {
+        &self.log
+    }
+
+    /// Creates a generator set up the way the recorded one was, ready for the first action. Errors if the log's config isn't
+    /// valid.
+    pub fn create_generator(&self) -> Result<DungeonGenerator, DungeonError> {
+        DungeonGenerator::new(self.log.config.clone())
+    }
+
+    /// The number of actions played so far
+    pub fn get_position(&self) -> usize {
+        self.next_action
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_action >= self.log.actions.len()
+    }
+
+    /// Plays the next action on the generator. Returns false if there was nothing left to play.
+    /// Only steps that succeeded were recorded, so a step that fails here means the replay has gone off course.
+    pub fn play_next(&mut self, generator: &mut DungeonGenerator) -> Result<bool, DungeonError> {
+        let action = match self.log.actions.get(self.next_action) {
+            None => return Ok(false),
+            Some(action) => action.clone(),
+        };
+
+        self.next_action += 1;
+        match action {
+            ReplayAction::Step => generator.step()?,
+            ReplayAction::ApplyTypes(types) => generator.apply_types(types),
+            ReplayAction::SetObservationStrategy(strategy) => generator.set_observation_strategy(strategy),
+            ReplayAction::StartAttempt(attempt) => generator.start_attempt(attempt),
+        }
+
+        Ok(true)
+    }
+
+    /// Plays every remaining action, stopping at the first error
+    pub fn play_to_end(&mut self, generator: &mut DungeonGenerator) -> Result<(), DungeonError> {
+        while self.play_next(generator)? {}
+
+        Ok(())
+    }
+
+    /// Checks the generator's grid against the hash stored in the log
+    pub fn verify(&self, generator: &DungeonGenerator) -> ReplayVerification {
+        if !self.is_finished() {
+            return ReplayVerification::Unfinished;
+        }
+
+        match self.log.final_grid_hash {
+            None => ReplayVerification::NoHash,
+            Some(expected) => {
+                let actual = generator.wfc.get_grid().content_hash();
+                if actual == expected { ReplayVerification::Matched } else { ReplayVerification::Mismatched { expected, actual } }
+            },
+        }
+    }
+}
+
+/// Writes a cell type as its kind and a 1 or 0 for each connection, like Hall:100100
+fn cell_type_to_text(cell_type: &DungeonCellType) -> String {
+    let connections: String = cell_type.connections().to_vec().iter().map(|connects| if *connects { '1' } else { '0' }).collect();
+    match cell_type {
+        DungeonCellType::None => String::from("None"),
+        DungeonCellType::Hall(_) => format!("Hall:{}", connections),
+        DungeonCellType::Room(_) => format!("Room:{}", connections),
+    }
+}
+
+fn cell_type_from_text(text: &str) -> Option<DungeonCellType> {
+    if text == "None" {
+        return Some(DungeonCellType::None);
+    }
+
+    let (kind, connections) = text.split_once(':')?;
+    if connections.len() != 6 || connections.chars().any(|c| c != '0' && c != '1') {
+        return None;
+    }
+
+    let connections = CellConnections::from_vec(connections.chars().map(|c| c == '1').collect());
+    match kind {
+        "Hall" => Some(DungeonCellType::Hall(connections)),
+        "Room" => Some(DungeonCellType::Room(connections)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished_replay_log() -> ReplayLog {
+        let mut generator = DungeonGenerator::new(DungeonGeneratorConfig { seed: 42, room_count: 3, ..Default::default() }).unwrap();
+        generator.generate().unwrap();
+        generator.get_replay_log()
+    }
+
+    #[test]
+    fn replay_logs_read_back_the_way_they_were_written() {
+        let log = ReplayLog {
+            config: DungeonGeneratorConfig { seed: 9, rows: 12, ..Default::default() },
+            actions: vec![
+                ReplayAction::Step,
+                ReplayAction::Step,
+                ReplayAction::ApplyTypes(vec![(GridLocation::new(2, 3), vec![DungeonCellType::None, DungeonCellType::Hall(CellConnections::new(true, false, false, true, false, false))])]),
+                ReplayAction::SetObservationStrategy(ObservationStrategy::Random),
+                ReplayAction::StartAttempt(1),
+                ReplayAction::Step,
+            ],
+            final_grid_hash: Some(1234),
+        };
+
+        assert_eq!(ReplayLog::from_text(&log.to_text()), Ok(log));
+    }
+
+    #[test]
+    fn replaying_a_finished_generation_matches_it() {
+        let log = ReplayLog::from_text(&finished_replay_log().to_text()).unwrap();
+        let mut player = ReplayPlayer::new(log);
+        let mut generator = player.create_generator().unwrap();
+        player.play_to_end(&mut generator).unwrap();
+
+        assert_eq!(player.verify(&generator), ReplayVerification::Matched);
+    }
+
+    #[test]
+    fn replaying_against_a_different_hash_is_a_mismatch() {
+        let mut log = finished_replay_log();
+        let expected = log.final_grid_hash.unwrap() ^ 1;
+        log.final_grid_hash = Some(expected);
+        let mut player = ReplayPlayer::new(log);
+        let mut generator = player.create_generator().unwrap();
+        player.play_to_end(&mut generator).unwrap();
+
+        assert_eq!(player.verify(&generator), ReplayVerification::Mismatched { expected, actual: expected ^ 1 });
+    }
+
+    #[test]
+    fn replaying_a_retried_generation_matches_it() {
+        let mut generator = DungeonGenerator::new(DungeonGeneratorConfig { seed: 17, generation_attempts: 5, ..Default::default() }).unwrap();
+        generator.generate().unwrap();
+        assert!(generator.get_attempt() > 0);
+
+        let mut player = ReplayPlayer::new(ReplayLog::from_text(&generator.get_replay_log().to_text()).unwrap());
+        let mut replayed = player.create_generator().unwrap();
+        player.play_to_end(&mut replayed).unwrap();
+
+        assert_eq!(player.verify(&replayed), ReplayVerification::Matched);
+        assert_eq!(replayed.get_attempt(), generator.get_attempt());
+    }
+}
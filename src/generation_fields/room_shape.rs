@@ -0,0 +1,282 @@
+use std::collections::HashSet;
+use crate::wfc::*;
+use crate::wfc::hex::*;
+use crate::wfc::random::*;
+use crate::generation_fields::dungeon::*;
+
+/// The different ways rooms can be shaped
+#[derive(Debug, Eq, Clone, PartialEq)]
+pub enum DungeonRoomShape {
+    /// A single-cell room
+    Single(CellConnections),
+    /// A cluster of three cells. Order is peak, bottom left, bottom right
+    Cluster3(CellConnections, CellConnections, CellConnections),
+    /// A center cell with all six of its neighbors around it
+    Flower,
+    /// A straight line of cells, heading from the room's location in a CellConnections direction. Direction, then length.
+    Line(usize, usize),
+    /// The cells a set number of steps from the room's location, leaving the middle open
+    Ring(usize),
+    /// Any set of cells, as offsets from the room's location
+    Custom(Vec<CubeCoord>),
+}
+
+impl DungeonRoomShape {
+    /// Where each of the room's cells is relative to its location, without repeats.
+    /// Single and Cluster3 keep their cells in the same order as their connections.
+    pub fn get_offsets(&self) -> Vec<CubeCoord> {
+        let origin = CubeCoord::new(0, 0);
+        let offsets = match self {
+            DungeonRoomShape::Single(_) => vec![origin],
+            DungeonRoomShape::Cluster3(_, _, _) => vec![
+                origin,
+                CubeCoord::direction(CellConnections::BOTTOM_LEFT),
+                CubeCoord::direction(CellConnections::BOTTOM_RIGHT),
+            ],
+            DungeonRoomShape::Flower => CubeCoord::spiral(&origin, 1),
+            DungeonRoomShape::Line(direction, length) => {
+                (0..*length as i64).map(|step| CubeCoord::direction(*direction).scale(step)).collect()
+            },
+            DungeonRoomShape::Ring(radius) => CubeCoord::ring(&origin, *radius as i64),
+            DungeonRoomShape::Custom(offsets) => offsets.clone(),
+        };
+
+        let mut seen = HashSet::new();
+        offsets.into_iter().filter(|offset| seen.insert(*offset)).collect()
+    }
+
+    /// The connections the shape was given for each cell, on top of the ones worked out from its layout.
+    /// Only Single and Cluster3 have any.
+    fn get_given_connections(&self) -> Vec<CellConnections> {
+        match self {
+            DungeonRoomShape::Single(connections) => vec![*connections],
+            DungeonRoomShape::Cluster3(peak, bottom_left, bottom_right) => vec![*peak, *bottom_left, *bottom_right],
+            _ => vec![],
+        }
+    }
+
+    /// Every side of a cell that faces out of the room, leaving out sides facing a hole the room closes in, like a ring's middle
+    pub fn get_outer_boundary(&self) -> Vec<RoomDoor> {
+        let offsets = self.get_offsets();
+        let cells: HashSet<CubeCoord> = offsets.iter().copied().collect();
+
+        // Flood in from just past the room's furthest cell. Anything outside the room that the flood can't reach is closed in.
+        let origin = CubeCoord::new(0, 0);
+        let radius = offsets.iter().map(|offset| offset.length()).max().unwrap_or(0) + 1;
+        let mut outside: HashSet<CubeCoord> = CubeCoord::ring(&origin, radius).into_iter().collect();
+        let mut queue: Vec<CubeCoord> = outside.iter().copied().collect();
+
+        while let Some(coord) = queue.pop() {
+            for direction in 0..6 {
+                let neighbor = coord.neighbor(direction);
+                if neighbor.length() <= radius && !cells.contains(&neighbor) && outside.insert(neighbor) {
+                    queue.push(neighbor);
+                }
+            }
+        }
+
+        offsets.iter().flat_map(|offset| {
+            (0..6).map(move |direction| RoomDoor { offset: *offset, direction })
+        }).filter(|door| outside.contains(&door.get_outside_offset())).collect()
+    }
+}
+
+/// A way into a room, through one side of one of its cells
+#[derive(Debug, Eq, Copy, Clone, PartialEq)]
+pub struct RoomDoor {
+    /// The cell the door is on, relative to the room's location
+    pub offset: CubeCoord,
+    /// The side the door is on, as a CellConnections direction
+    pub direction: usize,
+}
+
+impl RoomDoor {
+    /// Where the cell just outside the door is, relative to the room's location
+    pub fn get_outside_offset(&self) -> CubeCoord {
+        self.offset.neighbor(self.direction)
+    }
+}
+
+/// A descriptor for generating a room in the dungeon
+#[derive(Debug, Eq, Clone, PartialEq)]
+pub struct DungeonRoom {
+    pub shape: DungeonRoomShape,
+    pub location: GridLocation,
+    /// Connections out of the room. Cells in the room always connect to each other.
+    pub doors: Vec<RoomDoor>,
+}
+
+impl DungeonRoom {
+    /// Where each of the room's cells is on the grid
+    pub fn get_locations(&self) -> Vec<GridLocation> {
+        let origin = CubeCoord::from_location(&self.location);
+        self.shape.get_offsets().into_iter().map(|offset| (origin + offset).to_location()).collect()
+    }
+
+    /// The connections for each of the room's cells, in the same order as get_locations. Neighboring cells in the room
+    /// connect to each other, and each door connects its cell outward.
+    pub fn get_cell_connections(&self) -> Vec<CellConnections> {
+        let offsets = self.shape.get_offsets();
+        let given_connections = self.shape.get_given_connections();
+
+        offsets.iter().enumerate().map(|(index, offset)| {
+            let mut connections = match given_connections.get(index) {
+                Some(connections) => connections.to_vec(),
+                None => CellConnections::none().to_vec(),
+            };
+
+            for (direction, connects) in connections.iter_mut().enumerate() {
+                if offsets.contains(&offset.neighbor(direction)) {
+                    *connects = true;
+                }
+            }
+
+            for door in self.doors.iter().filter(|door| door.offset == *offset) {
+                connections[door.direction] = true;
+            }
+
+            CellConnections::from_vec(connections)
+        }).collect()
+    }
+}
+
+/// The kinds of room that get scattered around the dungeon, each picked by its weight in the config
+#[derive(Debug, Eq, Copy, Clone, PartialEq)]
+pub enum RoomKind {
+    Single,
+    Cluster3,
+    Flower,
+    Line,
+    Ring,
+}
+
+impl RoomKind {
+    pub const ALL: [RoomKind; 5] = [
+        RoomKind::Single,
+        RoomKind::Cluster3,
+        RoomKind::Flower,
+        RoomKind::Line,
+        RoomKind::Ring,
+    ];
+
+    pub fn get_label(&self) -> &'static str {
+        match self {
+            RoomKind::Single => "Single",
+            RoomKind::Cluster3 => "Cluster",
+            RoomKind::Flower => "Flower",
+            RoomKind::Line => "Line",
+            RoomKind::Ring => "Ring",
+        }
+    }
+
+    /// Makes a shape of this kind. Lines get a random direction and a length from 2 to 4.
+    pub fn random_shape(&self, random: &mut SeededRandom) -> DungeonRoomShape {
+        match self {
+            RoomKind::Single => DungeonRoomShape::Single(CellConnections::none()),
+            RoomKind::Cluster3 => DungeonRoomShape::Cluster3(CellConnections::none(), CellConnections::none(), CellConnections::none()),
+            RoomKind::Flower => DungeonRoomShape::Flower,
+            RoomKind::Line => DungeonRoomShape::Line(random.index_below(6), 2 + random.index_below(3)),
+            RoomKind::Ring => DungeonRoomShape::Ring(1),
+        }
+    }
+
+    /// Picks a kind at random, weighted by `weights`, which are in RoomKind::ALL order
+    pub fn pick(weights: &[f64; 5], random: &mut SeededRandom) -> RoomKind {
+        let total: f64 = weights.iter().sum();
+        let mut value = random.range_f64(0.0, total);
+
+        for (kind, weight) in RoomKind::ALL.iter().zip(weights) {
+            if value < *weight {
+                return *kind;
+            }
+
+            value -= weight;
+        }
+
+        // Rounding can leave a sliver past the last weight, so fall back to the last kind that can be picked
+        *RoomKind::ALL.iter().zip(weights).rev().find(|(_, weight)| **weight > 0.0).unwrap().0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room_at_origin(shape: DungeonRoomShape, doors: Vec<RoomDoor>) -> DungeonRoom {
+        DungeonRoom { shape, location: GridLocation::new(0, 0), doors }
+    }
+
+    fn assert_connections_are_symmetric(shape: DungeonRoomShape) {
+        let room = room_at_origin(shape, vec![]);
+        let offsets = room.shape.get_offsets();
+        let connections = room.get_cell_connections();
+
+        for (index, offset) in offsets.iter().enumerate() {
+            for direction in 0..6 {
+                if let Some(neighbor_index) = offsets.iter().position(|other| *other == offset.neighbor(direction)) {
+                    let there = connections[neighbor_index].to_vec()[CellConnections::opposite_index(direction)];
+                    assert_eq!(connections[index].to_vec()[direction], there, "{:?} toward {}", offset, direction);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_ring_never_has_doors_on_its_inner_edge() {
+        let origin = CubeCoord::new(0, 0);
+        let boundary = DungeonRoomShape::Ring(1).get_outer_boundary();
+
+        assert!(!boundary.is_empty());
+        assert!(boundary.iter().all(|door| door.get_outside_offset() != origin));
+    }
+
+    #[test]
+    fn flower_connections_are_symmetric() {
+        assert_connections_are_symmetric(DungeonRoomShape::Flower);
+    }
+
+    #[test]
+    fn line_connections_are_symmetric() {
+        for direction in 0..6 {
+            assert_connections_are_symmetric(DungeonRoomShape::Line(direction, 4));
+        }
+    }
+
+    #[test]
+    fn custom_shapes_drop_repeated_offsets() {
+        let offsets = vec![CubeCoord::new(0, 0), CubeCoord::new(1, 0), CubeCoord::new(0, 0), CubeCoord::new(1, 0)];
+        let room = room_at_origin(DungeonRoomShape::Custom(offsets), vec![]);
+
+        assert_eq!(room.shape.get_offsets(), vec![CubeCoord::new(0, 0), CubeCoord::new(1, 0)]);
+        assert_eq!(room.get_locations().len(), 2);
+        assert_eq!(room.get_cell_connections().len(), 2);
+    }
+
+    #[test]
+    fn doors_only_go_on_outer_boundary_sides() {
+        let mut generator = DungeonGenerator::new(Default::default()).unwrap();
+        let location = GridLocation::new(7, 10);
+        let shapes = vec![
+            DungeonRoomShape::Single(CellConnections::none()),
+            DungeonRoomShape::Flower,
+            DungeonRoomShape::Line(CellConnections::RIGHT, 3),
+            DungeonRoomShape::Ring(1),
+        ];
+
+        for shape in shapes {
+            let boundary = shape.get_outer_boundary();
+            let room = DungeonRoom { doors: generator.choose_doors(&shape, location, 6), shape, location };
+            assert!(room.doors.iter().all(|door| boundary.contains(door)), "{:?}", room.shape);
+
+            // Every connection leading out of the room is one of its doors
+            let offsets = room.shape.get_offsets();
+            for (offset, connections) in offsets.iter().zip(room.get_cell_connections()) {
+                for (direction, connects) in connections.to_vec().into_iter().enumerate() {
+                    if connects && !offsets.contains(&offset.neighbor(direction)) {
+                        assert!(room.doors.contains(&RoomDoor { offset: *offset, direction }));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::wfc::*;
+use crate::wfc::adjacency::*;
+
+/// The kinds of ground a terrain map is made of
+#[derive(Debug, Eq, Copy, Clone, PartialEq)]
+pub enum TerrainCellType {
+    Beach,
+    Sea,
+    Land,
+}
+
+/// Land can only touch land and beach, sea can only touch sea and beach, and beach can touch anything
+pub fn terrain_rules() -> AdjacencyRules<TerrainCellType> {
+    let mut rules = AdjacencyRules::new(&[TerrainCellType::Beach, TerrainCellType::Land, TerrainCellType::Sea]);
+    rules.allow_all_directions(&TerrainCellType::Land, &TerrainCellType::Land);
+    rules.allow_all_directions(&TerrainCellType::Land, &TerrainCellType::Beach);
+    rules.allow_all_directions(&TerrainCellType::Sea, &TerrainCellType::Sea);
+    rules.allow_all_directions(&TerrainCellType::Sea, &TerrainCellType::Beach);
+    rules.allow_all_directions(&TerrainCellType::Beach, &TerrainCellType::Beach);
+    rules
+}
+
+/// The terrain adjacency rules, plus some shaping to grow land and sea into larger regions
+pub fn terrain_reducer() -> Box<dyn WaveFunctionReducer<TerrainCellType>> {
+    let rules = terrain_rules();
+
+    Box::new(move |loc: &GridLocation, cell: &PendingCell, grid: &HexGrid<TerrainCellType>| {
+        let reduced_types = rules.reduce(loc, cell, grid);
+        let mut changed = reduced_types.is_some();
+        let mut possible_types = reduced_types.unwrap_or_else(|| cell.possible_types.clone());
+
+        // On top of the adjacency rules, beach surrounded by land becomes land (surrounded by sea can be a small island,
+        // sand bar, etc.)
+
+        let registry = grid.get_registry();
+        let neighbors: Vec<MaybeCell> = grid.get_neighbors(loc).iter().map(|location| grid.get_cell(location)).collect();
+
+        let cell_has_neighbor_of_type = |cell_type: TerrainCellType| {
+            neighbors.iter().any(|neighbor| match neighbor { None => false, Some(neighbor) => {
+                neighbor.possible_types.count() == 1 && registry.contains(&neighbor.possible_types, &cell_type)
+            }} )
+        };
+
+        let all_neighbors_are = |cell_type: TerrainCellType| {
+            neighbors.iter().all(|neighbor| match neighbor { None => false, Some(neighbor) => {
+                neighbor.possible_types.count() == 1 && registry.contains(&neighbor.possible_types, &cell_type)
+            }} )
+        };
+
+        if registry.contains(&possible_types, &TerrainCellType::Beach) && all_neighbors_are(TerrainCellType::Land) {
+            possible_types = registry.set_of(&[TerrainCellType::Land]);
+            changed = true;
+        }
+
+        // This might not technically belong here...re-evaluate for non-toy application
+
+        // We generally want land tiles to generate more land and sea tiles to generate more sea.
+        // If we were just narrowed down, possibly just keep building on that.
+        if changed && possible_types.count() == 2 {
+            if registry.contains(&possible_types, &TerrainCellType::Land) && cell_has_neighbor_of_type(TerrainCellType::Land) {
+                possible_types = registry.set_of(&[TerrainCellType::Land]);
+            } else if registry.contains(&possible_types, &TerrainCellType::Sea) && cell_has_neighbor_of_type(TerrainCellType::Sea) {
+                possible_types = registry.set_of(&[TerrainCellType::Sea]);
+            }
+        }
+
+        if changed { Some(possible_types) } else { None }
+    })
+}
+
+/// How likely each type is to be picked when filling in terrain. Beach mostly shows up on its own between land and sea.
+pub fn terrain_weight(cell_type: &TerrainCellType) -> f64 {
+    match cell_type {
+        TerrainCellType::Beach => 1.0,
+        TerrainCellType::Sea => 3.0,
+        TerrainCellType::Land => 3.0,
+    }
+}
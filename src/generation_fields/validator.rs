@@ -0,0 +1,246 @@
+use std::fmt;
+use std::collections::VecDeque;
+use crate::wfc::*;
+use crate::generation_fields::dungeon::*;
+
+/// Something wrong with a finished dungeon
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DungeonProblem {
+    /// The goal can't be walked to from the start
+    UnreachableGoal(GridLocation),
+    /// The cell connects toward a neighbor that doesn't connect back. Location, then the CellConnections direction.
+    OneSidedConnection(GridLocation, usize),
+    /// A contradiction left the cell with no types, and the dungeon leads into it
+    EmptyCell(GridLocation),
+    /// A room has a door into a None cell or off the grid. Location, then the CellConnections direction.
+    DoorIntoNothing(GridLocation, usize),
+}
+
+impl DungeonProblem {
+    /// The cell the problem is on
+    pub fn get_location(&self) -> GridLocation {
+        match self {
+            DungeonProblem::UnreachableGoal(location) => *location,
+            DungeonProblem::OneSidedConnection(location, _) => *location,
+            DungeonProblem::EmptyCell(location) => *location,
+            DungeonProblem::DoorIntoNothing(location, _) => *location,
+        }
+    }
+}
+
+impl fmt::Display for DungeonProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DungeonProblem::UnreachableGoal(location) => {
+                write!(f, "The goal at ({}, {}) can't be reached from the start", location.row, location.col)
+            },
+            DungeonProblem::OneSidedConnection(location, direction) => {
+                write!(f, "The cell at ({}, {}) connects {} but its neighbor doesn't connect back", location.row, location.col, direction_name(*direction))
+            },
+            DungeonProblem::EmptyCell(location) => {
+                write!(f, "The cell at ({}, {}) has no types left", location.row, location.col)
+            },
+            DungeonProblem::DoorIntoNothing(location, direction) => {
+                write!(f, "The room at ({}, {}) has a door {} that leads nowhere", location.row, location.col, direction_name(*direction))
+            },
+        }
+    }
+}
+
+fn direction_name(direction: usize) -> &'static str {
+    match direction {
+        CellConnections::TOP_LEFT => "top left",
+        CellConnections::TOP_RIGHT => "top right",
+        CellConnections::RIGHT => "right",
+        CellConnections::BOTTOM_RIGHT => "bottom right",
+        CellConnections::BOTTOM_LEFT => "bottom left",
+        _ => "left",
+    }
+}
+
+/// Checks a finished dungeon for problems generation can leave behind.
+/// Cells with more than one type left are skipped, so this is only meaningful once the grid has settled.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DungeonValidator {
+    pub start_location: GridLocation,
+    pub goal_location: GridLocation,
+}
+
+impl DungeonValidator {
+    pub fn new(start_location: GridLocation, goal_location: GridLocation) -> DungeonValidator {
+        DungeonValidator {
+            start_location,
+            goal_location,
+        }
+    }
+
+    /// Every problem on the grid. An empty list means the dungeon is good.
+    pub fn validate(&self, grid: &HexGrid<DungeonCellType>) -> Vec<DungeonProblem> {
+        let mut problems = self.find_connection_problems(grid);
+        problems.append(&mut self.find_reachability_problems(grid));
+
+        problems
+    }
+
+    /// One-sided connections and doors into nothing. Connections into empty cells are left to the reachability check.
+    fn find_connection_problems(&self, grid: &HexGrid<DungeonCellType>) -> Vec<DungeonProblem> {
+        let mut problems = vec![];
+
+        for cell in grid.cells() {
+            let cell_type = match settled_cell_type(grid, &cell.location) {
+                Some(cell_type) => cell_type,
+                None => continue,
+            };
+
+            let is_room = matches!(cell_type, DungeonCellType::Room(_));
+            let connections = cell_type.connections().to_vec();
+
+            for (direction, neighbor) in cell.location.get_neighbors().into_iter().enumerate() {
+                if !connections[direction] {
+                    continue;
+                }
+
+                let leads_nowhere = match grid.get_cell(&neighbor) {
+                    None => true,
+                    Some(neighbor_cell) if neighbor_cell.possible_types.is_empty() => continue,
+                    Some(_) => match settled_cell_type(grid, &neighbor) {
+                        None => continue,
+                        Some(DungeonCellType::None) => true,
+                        Some(neighbor_type) => {
+                            if !neighbor_type.connections().to_vec()[CellConnections::opposite_index(direction)] {
+                                problems.push(DungeonProblem::OneSidedConnection(cell.location, direction));
+                            }
+
+                            continue;
+                        },
+                    },
+                };
+
+                if leads_nowhere {
+                    problems.push(if is_room {
+                        DungeonProblem::DoorIntoNothing(cell.location, direction)
+                    } else {
+                        DungeonProblem::OneSidedConnection(cell.location, direction)
+                    });
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Walks from the start through connections that go both ways. The goal has to be on the way, and any empty cell the
+    /// walk leads into is a contradiction. Clean empties every cell it can't reach, so empty cells off the walk are fine.
+    fn find_reachability_problems(&self, grid: &HexGrid<DungeonCellType>) -> Vec<DungeonProblem> {
+        let mut problems = vec![];
+        let mut is_reached = vec![vec![false; grid.get_col_count()]; grid.get_row_count()];
+
+        let mut queue = VecDeque::<GridLocation>::new();
+        if grid.get_cell(&self.start_location).is_some() {
+            is_reached[self.start_location.row as usize][self.start_location.col as usize] = true;
+            queue.push_back(self.start_location);
+        }
+
+        while let Some(location) = queue.pop_front() {
+            let connections = match settled_cell_type(grid, &location) {
+                Some(cell_type) => cell_type.connections().to_vec(),
+                None => continue,
+            };
+
+            for (direction, neighbor) in location.get_neighbors().into_iter().enumerate() {
+                let neighbor_cell = match grid.get_cell(&neighbor) {
+                    Some(neighbor_cell) if connections[direction] => neighbor_cell,
+                    _ => continue,
+                };
+
+                if is_reached[neighbor.row as usize][neighbor.col as usize] {
+                    continue;
+                }
+
+                if neighbor_cell.possible_types.is_empty() {
+                    is_reached[neighbor.row as usize][neighbor.col as usize] = true;
+                    problems.push(DungeonProblem::EmptyCell(neighbor));
+                    continue;
+                }
+
+                let connects_back = match settled_cell_type(grid, &neighbor) {
+                    Some(neighbor_type) => neighbor_type.connections().to_vec()[CellConnections::opposite_index(direction)],
+                    None => false,
+                };
+
+                if connects_back {
+                    is_reached[neighbor.row as usize][neighbor.col as usize] = true;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        let goal_is_reached = grid.get_cell(&self.goal_location).is_some() && is_reached[self.goal_location.row as usize][self.goal_location.col as usize];
+        if !goal_is_reached {
+            problems.push(DungeonProblem::UnreachableGoal(self.goal_location));
+        }
+
+        problems
+    }
+}
+
+/// The cell's type, if it's narrowed down to exactly one
+fn settled_cell_type(grid: &HexGrid<DungeonCellType>, location: &GridLocation) -> Option<DungeonCellType> {
+    grid.get_cell(location).and_then(|cell| cell.settled_type()).map(|index| *grid.get_registry().get(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+
+    /// A single row of cells with the given types, left to right
+    fn row_of(types: [DungeonCellType; 3]) -> WaveFunctionCollapseContext<DungeonCellType> {
+        let mut wfc = WaveFunctionCollapseContext::with_registry(1, 3, Arc::new(DUNGEON_CELL_REGISTRY.clone()));
+        wfc.apply_fixed_types(types.into_iter().enumerate().map(|(col, cell_type)| (GridLocation::new(0, col as i64), vec![cell_type])).collect());
+
+        wfc
+    }
+
+    fn right() -> CellConnections {
+        CellConnections::new(false, false, true, false, false, false)
+    }
+
+    fn left() -> CellConnections {
+        CellConnections::new(false, false, false, false, false, true)
+    }
+
+    #[test]
+    fn a_goal_the_start_cant_reach_is_reported() {
+        let wfc = row_of([DungeonCellType::Hall(right()), DungeonCellType::Hall(left()), DungeonCellType::Room(CellConnections::none())]);
+        let validator = DungeonValidator::new(GridLocation::new(0, 0), GridLocation::new(0, 2));
+
+        assert_eq!(validator.validate(wfc.get_grid()), vec![DungeonProblem::UnreachableGoal(GridLocation::new(0, 2))]);
+    }
+
+    #[test]
+    fn a_connection_the_neighbor_doesnt_return_is_one_sided() {
+        let wfc = row_of([DungeonCellType::Hall(right()), DungeonCellType::Hall(right()), DungeonCellType::Hall(left())]);
+        let validator = DungeonValidator::new(GridLocation::new(0, 0), GridLocation::new(0, 0));
+
+        assert_eq!(validator.validate(wfc.get_grid()), vec![DungeonProblem::OneSidedConnection(GridLocation::new(0, 0), CellConnections::RIGHT)]);
+    }
+
+    #[test]
+    fn a_path_into_an_emptied_cell_is_reported() {
+        let mut wfc = row_of([DungeonCellType::Hall(right()), DungeonCellType::Hall(left()), DungeonCellType::None]);
+        let empty_types = wfc.get_grid().get_registry().none();
+        wfc.set_cell_types(&GridLocation::new(0, 1), empty_types);
+        let validator = DungeonValidator::new(GridLocation::new(0, 0), GridLocation::new(0, 0));
+
+        assert_eq!(validator.validate(wfc.get_grid()), vec![DungeonProblem::EmptyCell(GridLocation::new(0, 1))]);
+    }
+
+    #[test]
+    fn a_room_door_into_a_none_cell_leads_into_nothing() {
+        let wfc = row_of([DungeonCellType::Room(right()), DungeonCellType::None, DungeonCellType::None]);
+        let validator = DungeonValidator::new(GridLocation::new(0, 0), GridLocation::new(0, 0));
+
+        assert_eq!(validator.validate(wfc.get_grid()), vec![DungeonProblem::DoorIntoNothing(GridLocation::new(0, 0), CellConnections::RIGHT)]);
+    }
+}
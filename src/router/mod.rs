@@ -7,6 +7,8 @@ pub enum Route {
     WFCDungeonSandbox,
     #[at("/terrain")]
     WFCSandbox,
+    #[at("/terrain/infinite")]
+    InfiniteTerrain,
     #[not_found]
     #[at("/404")]
     NotFound,
@@ -16,6 +18,7 @@ pub fn router(routes: Route) -> Html {
     match routes {
         Route::WFCDungeonSandbox => html! { <crate::views::wfc_dungeon_sandbox::WFCDungeonSandbox /> },
         Route::WFCSandbox => html! { <crate::views::wfc_sandbox::WFCSandbox /> },
+        Route::InfiniteTerrain => html! { <crate::views::infinite_terrain::InfiniteTerrain /> },
         Route::NotFound => html! { <crate::views::not_found::NotFound /> },
     }
 }
@@ -0,0 +1,154 @@
+use yew::prelude::*;
+use crate::wfc::*;
+use crate::wfc::chunked::*;
+use crate::generation_fields::terrain::*;
+use crate::components::basic_hex_cell::*;
+use crate::views::wfc_sandbox::terrain_color;
+use crate::views::is_drawn_row_shifted;
+use web_sys::{EventTarget, HtmlInputElement};
+use wasm_bindgen::JsCast;
+
+const CHUNK_SIZE: usize = 16;
+const VIEW_ROWS: i64 = 24;
+const VIEW_COLS: i64 = 40;
+/// How far each scroll button moves the view. Even, so rows keep their shift.
+const SCROLL_STEP: i64 = 8;
+
+pub enum Msg {
+    Scroll(i64, i64),
+    SeedInputChanged(u64),
+}
+
+#[derive(PartialEq, Properties)]
+pub struct InfiniteTerrainProps {
+}
+
+pub struct InfiniteTerrain {
+    world: InfiniteWorld<TerrainCellType>,
+    reducer: Box<dyn WaveFunctionReducer<TerrainCellType>>,
+    /// The world location of the top left cell in view
+    view_origin: GridLocation,
+    pub seed_string: String,
+}
+
+fn new_world(seed: u64) -> InfiniteWorld<TerrainCellType> {
+    InfiniteWorld::new(seed, CHUNK_SIZE, CHUNK_SIZE, terrain_rules().get_shared_registry(), &terrain_weight)
+        .expect("CHUNK_SIZE fits pointy top hexes")
+}
+
+impl InfiniteTerrain {
+    /// Generates the chunks in view and drops the rest
+    fn generate_view(&mut self) {
+        let bottom_right = GridLocation::new(self.view_origin.row + VIEW_ROWS - 1, self.view_origin.col + VIEW_COLS - 1);
+        self.world.generate_area(&self.view_origin, &bottom_right, self.reducer.as_mut());
+
+        let (first_chunk, _) = self.world.chunk_location_of(&self.view_origin);
+        let (last_chunk, _) = self.world.chunk_location_of(&bottom_right);
+        self.world.unload_outside(&first_chunk, &last_chunk);
+    }
+}
+
+impl Component for InfiniteTerrain {
+    type Message = Msg;
+    type Properties = InfiniteTerrainProps;
+
+    fn create(_ctx: &Context<Self>) -> Self {
+        let mut data = InfiniteTerrain {
+            world: new_world(0),
+            reducer: terrain_reducer(),
+            view_origin: GridLocation::new(0, 0),
+            seed_string: String::from("0"),
+        };
+
+        data.generate_view();
+
+        data
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let scroll_up = ctx.link().callback(|_| Msg::Scroll(-SCROLL_STEP, 0));
+        let scroll_down = ctx.link().callback(|_| Msg::Scroll(SCROLL_STEP, 0));
+        let scroll_left = ctx.link().callback(|_| Msg::Scroll(0, -SCROLL_STEP));
+        let scroll_right = ctx.link().callback(|_| Msg::Scroll(0, SCROLL_STEP));
+        let seed_changed = {
+            let on_seed_changed = ctx.link().callback(|val: u64| Msg::SeedInputChanged(val));
+            Callback::from(move |e: InputEvent| {
+                let target: Option<EventTarget> = e.target();
+                let input = target.and_then(|t| t.dyn_into::<HtmlInputElement>().ok());
+
+                if let Some(input) = input {
+                    if let Ok(seed) = input.value().parse::<u64>() {
+                        on_seed_changed.emit(seed);
+                    }
+                }
+            })
+        };
+
+        let registry = self.world.get_registry();
+
+        html! {
+            <div>
+                <div class={classes!("config-panel")}>
+                    <button onclick={scroll_up}>{"Up"}</button>
+                    <button onclick={scroll_down}>{"Down"}</button>
+                    <button onclick={scroll_left}>{"Left"}</button>
+                    <button onclick={scroll_right}>{"Right"}</button>
+                    <div>{format!("Position: {}, {}", self.view_origin.row, self.view_origin.col)}</div>
+                    <input type={"number"} min={0} value={self.seed_string.clone()} oninput={seed_changed} />
+                </div>
+                <div class={classes!("wfc-sandbox-hex-grid")}>
+                {
+                    (self.view_origin.row..self.view_origin.row + VIEW_ROWS).map(|row_index| {
+                        let is_odd = !is_drawn_row_shifted(self.world.get_topology(), row_index);
+
+                        let row_class = classes!(
+                            "wfc-sandbox-grid-row",
+                            if is_odd { "wfc-sandbox-grid-row-odd" } else { "wfc-sandbox-grid-row-even" }
+                        );
+
+                        html! {
+                            <div class={row_class}>
+                            {
+                                (self.view_origin.col..self.view_origin.col + VIEW_COLS).map(|col_index| {
+                                    // Anything not generated yet, or that hit a contradiction, shows up black
+                                    let color = match self.world.get_types(&GridLocation::new(row_index, col_index)) {
+                                        Some(types) if types.count() == 1 => terrain_color(registry.get(types.first().unwrap())),
+                                        _ => "black",
+                                    };
+
+                                    html! {
+                                        <div class={classes!("wfc-sandbox-grid-cell-container")}>
+                                            <div class={classes!("wfc-sandbox-grid-cell-container-outer")}>
+                                                <div class={classes!("wfc-sandbox-grid-cell-container-inner")}>
+                                                    <BasicHexCell color={color} />
+                                                </div>
+                                            </div>
+                                        </div>
+                                    }
+                                }).collect::<Html>()
+                            }
+                            </div>
+                        }
+                    }).collect::<Html>()
+                }
+                </div>
+            </div>
+        }
+    }
+
+    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::Scroll(rows, cols) => {
+                self.view_origin = GridLocation::new(self.view_origin.row + rows, self.view_origin.col + cols);
+            },
+            Msg::SeedInputChanged(seed) => {
+                self.world = new_world(seed);
+                self.seed_string = seed.to_string();
+            },
+        };
+
+        self.generate_view();
+
+        true
+    }
+}
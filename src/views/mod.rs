@@ -1,3 +1,13 @@
+use crate::wfc::topology::*;
+
+pub mod infinite_terrain;
 pub mod not_found;
 pub mod wfc_dungeon_sandbox;
 pub mod wfc_sandbox;
+
+/// Whether a row of a drawn hex grid sits half a cell to the right. Cells are drawn as pointy top hexes in rows, so flat
+/// top grids, which shift columns instead, can't be drawn.
+pub fn is_drawn_row_shifted(topology: &HexTopology, row: i64) -> bool {
+    assert_eq!(topology.orientation, HexOrientation::PointyTop, "Only pointy top hex grids can be drawn");
+    topology.is_row_shifted(row)
+}
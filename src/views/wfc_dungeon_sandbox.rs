@@ -1,9 +1,17 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
 use yew::prelude::*;
 use gloo_timers::callback::Timeout;
 use crate::wfc::*;
+use crate::wfc::events::*;
 use crate::components::dungeon_cell::*;
+use crate::generation_fields::cell_probability::*;
 use crate::generation_fields::dungeon::*;
-use web_sys::{EventTarget, HtmlInputElement};
+use crate::generation_fields::dungeon_config::*;
+use crate::generation_fields::replay::*;
+use crate::generation_fields::room_shape::*;
+use crate::views::is_drawn_row_shifted;
+use web_sys::{EventTarget, HtmlInputElement, HtmlSelectElement, HtmlTextAreaElement};
 use wasm_bindgen::JsCast;
 
 pub enum Msg {
@@ -14,9 +22,37 @@ pub enum Msg {
     GenerateInstant,
     SetPaint(DungeonCellType),
     SetCell(GridLocation),
-    SeedInputChanged(u64),
+    ToggleObservationStrategy,
+    SettingChanged(DungeonSetting, String),
+    CellWeightChanged(CellCategory, String),
+    ResetCellWeights,
+    ClearNeighborWeights,
+    AddFitterExample,
+    ClearFitterExamples,
+    ToggleFitNeighbors,
+    FitCellWeights,
+    ExportProfile,
+    LoadProfile,
+    ApplySettings,
+    ExportSettings,
+    SettingsTextChanged(String),
+    LoadSettings,
+    StepBack,
+    ToggleTrace,
+    ExportReplay,
+    ReplayTextChanged(String),
+    LoadReplay,
+    ReplayStep,
+    ReplayToEnd,
 }
 
+/// How many steps back the undo history goes
+const UNDO_HISTORY_LIMIT: usize = 100;
+/// How many of the most recent events the trace panel keeps
+const TRACE_LIMIT: usize = 200;
+/// How long, in em, the cell weight bar for a category that gets every pick would be
+const WEIGHT_BAR_LENGTH: f64 = 15.0;
+
 
 #[derive(PartialEq, Properties)]
 pub struct WFCSandboxProps {
@@ -25,11 +61,49 @@ pub struct WFCSandboxProps {
 pub struct WFCDungeonSandbox {
     generator: DungeonGenerator,
     pub selected_set_cell_type: DungeonCellType,
-    pub seed_string: String,
+    /// What's typed into the settings panel, kept as text so half-typed values aren't lost
+    setting_values: HashMap<DungeonSetting, String>,
+    /// The cell weights being edited. These are set with sliders rather than typed.
+    cell_weights: CellProbabilityModel,
+    /// Learns cell weights from dungeons added as examples
+    fitter: CellProbabilityFitter,
+    pub fit_neighbors: bool,
+    /// Says how fitting, or saving and loading a profile, went
+    pub profile_status: String,
+    /// The config saved or loaded in the settings panel, as text
+    pub settings_text: String,
+    /// Says what's wrong with the typed settings, if anything
+    pub settings_status: String,
+    /// Snapshots from before each change, oldest first
+    undo_history: VecDeque<DungeonSnapshot>,
+    /// Every event from the generator's context, for the trace panel
+    event_log: Arc<Mutex<WfcEventLog>>,
+    pub show_trace: bool,
+    /// The replay being exported or loaded, as text so it can be copied out or pasted in
+    pub replay_text: String,
+    replay_player: Option<ReplayPlayer>,
+    pub replay_status: String,
+    /// Why generation stopped, shown until the generator is reset or stepped back
+    pub error_message: Option<String>,
+}
+
+fn new_generator(config: DungeonGeneratorConfig, event_log: &Arc<Mutex<WfcEventLog>>) -> DungeonGenerator {
+    let mut generator = DungeonGenerator::new(config).expect("Settings are checked before a generator is made from them");
+    generator.wfc.subscribe(event_log.clone());
+    generator
 }
 
-fn new_generator() -> DungeonGenerator {
-    DungeonGenerator::new(15, 20)
+/// Gets the text of whatever input or select an event came from
+fn on_value_changed(callback: Callback<String>) -> Callback<Event> {
+    Callback::from(move |e: Event| {
+        let target: Option<EventTarget> = e.target();
+
+        if let Some(input) = target.clone().and_then(|t| t.dyn_into::<HtmlInputElement>().ok()) {
+            callback.emit(input.value());
+        } else if let Some(select) = target.and_then(|t| t.dyn_into::<HtmlSelectElement>().ok()) {
+            callback.emit(select.value());
+        }
+    })
 }
 
 impl Component for WFCDungeonSandbox {
@@ -37,13 +111,28 @@ impl Component for WFCDungeonSandbox {
     type Properties = WFCSandboxProps;
 
     fn create(_ctx: &Context<Self>) -> Self {
+        let event_log = Arc::new(Mutex::new(WfcEventLog::new(TRACE_LIMIT)));
+        let config = DungeonGeneratorConfig::default();
         let mut data = WFCDungeonSandbox {
-            generator: new_generator(),
+            generator: new_generator(config.clone(), &event_log),
             selected_set_cell_type: DungeonCellType::None,
-            seed_string: String::from(""),
+            setting_values: HashMap::new(),
+            cell_weights: CellProbabilityModel::default(),
+            fitter: CellProbabilityFitter::new(),
+            fit_neighbors: false,
+            profile_status: String::from(""),
+            settings_text: String::from(""),
+            settings_status: String::from(""),
+            undo_history: VecDeque::new(),
+            event_log,
+            show_trace: false,
+            replay_text: String::from(""),
+            replay_player: None,
+            replay_status: String::from(""),
+            error_message: None,
         };
 
-        data.seed_string = data.generator.seed.to_string();
+        data.show_settings(&config);
 
         data
     }
@@ -56,19 +145,26 @@ impl Component for WFCDungeonSandbox {
         let select_set_hall = ctx.link().callback(|_| Msg::SetPaint(DungeonCellType::Hall(CellConnections::all())));
         let select_set_room = ctx.link().callback(|_| Msg::SetPaint(DungeonCellType::Room(CellConnections::all())));
         let reset = ctx.link().callback(|_| Msg::Reset);
-        let seed_changed = {
-            let on_seed_changed = ctx.link().callback(|val: u64| Msg::SeedInputChanged(val));
+        let step_back = ctx.link().callback(|_| Msg::StepBack);
+        let toggle_trace = ctx.link().callback(|_| Msg::ToggleTrace);
+        let export_replay = ctx.link().callback(|_| Msg::ExportReplay);
+        let load_replay = ctx.link().callback(|_| Msg::LoadReplay);
+        let replay_step = ctx.link().callback(|_| Msg::ReplayStep);
+        let replay_to_end = ctx.link().callback(|_| Msg::ReplayToEnd);
+        let replay_text_changed = {
+            let on_replay_text_changed = ctx.link().callback(|text: String| Msg::ReplayTextChanged(text));
             Callback::from(move |e: InputEvent| {
                 let target: Option<EventTarget> = e.target();
-                let input = target.and_then(|t| t.dyn_into::<HtmlInputElement>().ok());
+                let input = target.and_then(|t| t.dyn_into::<HtmlTextAreaElement>().ok());
 
                 if let Some(input) = input {
-                    on_seed_changed.emit(input.value().parse::<u64>().unwrap());
+                    on_replay_text_changed.emit(input.value());
                 }
             })
         };
+        let toggle_observation_strategy = ctx.link().callback(|_| Msg::ToggleObservationStrategy);
 
-        let grid = &self.generator.wfc.get_grid().grid;
+        let grid = self.generator.wfc.get_grid();
 
         let cell_type_name = match self.selected_set_cell_type {
             DungeonCellType::None => "None",
@@ -76,8 +172,17 @@ impl Component for WFCDungeonSandbox {
             DungeonCellType::Room(_) => "Room",
         };
 
-        let queued_cell_locations = self.generator.wfc.get_queue();
-        let can_do_more_work = self.generator.can_do_more_work();
+        let observation_strategy_name = match self.generator.observation_strategy {
+            ObservationStrategy::Random => "Random",
+            ObservationStrategy::MinEntropy => "Min Entropy",
+        };
+
+        let has_error = self.error_message.is_some();
+        let can_do_more_work = self.generator.can_do_more_work() && !has_error;
+        // Problems only make sense once the dungeon is finished
+        let problems = if self.generator.can_do_more_work() || has_error { vec![] } else { self.generator.validate() };
+        let problem_locations: Vec<GridLocation> = problems.iter().map(|problem| problem.get_location()).collect();
+        let can_replay = self.replay_player.as_ref().is_some_and(|player| !player.is_finished()) && !has_error;
         let mut row_index = 0;
 
         html! {
@@ -90,15 +195,45 @@ impl Component for WFCDungeonSandbox {
                     <button disabled={!can_do_more_work} onclick={step}>{"Step"}</button>
                     <button disabled={!can_do_more_work} onclick={step_complete}>{"Step To End"}</button>
                     <button disabled={!can_do_more_work} onclick={generate_instant}>{"Generate (Instant)"}</button>
-                    <button disabled={can_do_more_work} onclick={reset}>{"Reset"}</button><br />
-                    <div>{format!("State: {}", self.generator.debug_state())}</div>
-                    <input type={"number"} min={0} value={self.seed_string.clone()} oninput={seed_changed} />
+                    <button disabled={can_do_more_work} onclick={reset}>{"Reset"}</button>
+                    <button disabled={self.undo_history.is_empty()} onclick={step_back}>{format!("Step Back ({})", self.undo_history.len())}</button>
+                    <button onclick={toggle_observation_strategy}>{format!("Fill: {}", observation_strategy_name)}</button><br />
+                    <div>{format!("State: {} ({:.0}%)", self.generator.debug_state(), self.generator.get_progress() * 100.0)}</div>
+                    <div>{format!("Backtracks: {}", self.generator.wfc.get_backtrack_count())}</div>
+                    <div>{format!(
+                        "Attempt: {} of {} (seed {})",
+                        self.generator.get_attempt() + 1,
+                        self.generator.get_config().generation_attempts,
+                        self.generator.get_attempt_seed(),
+                    )}</div>
+                    <button onclick={toggle_trace}>{format!("Trace: {}", if self.show_trace { "On" } else { "Off" })}</button>
+                    if let Some(message) = &self.error_message {
+                        <div class={classes!("wfc-ds-error")}>{message.clone()}</div>
+                    }
+                    if !self.generator.can_do_more_work() && !has_error {
+                        <div>{format!("Problems: {}", problems.len())}</div>
+                        <ul class={classes!("wfc-ds-problems")}>
+                            { problems.iter().map(|problem| html! { <li>{problem.to_string()}</li> }).collect::<Html>() }
+                        </ul>
+                    }
+                </div>
+                { self.view_settings(ctx) }
+                if self.show_trace {
+                    { self.view_trace() }
+                }
+                <div class={classes!("config-panel")}>
+                    <button onclick={export_replay}>{"Export Replay"}</button>
+                    <button onclick={load_replay}>{"Load Replay"}</button>
+                    <button disabled={!can_replay} onclick={replay_step}>{"Replay Step"}</button>
+                    <button disabled={!can_replay} onclick={replay_to_end}>{"Replay To End"}</button>
+                    <div>{self.replay_status.clone()}</div>
+                    <textarea rows={"4"} cols={"60"} value={self.replay_text.clone()} oninput={replay_text_changed} />
                 </div>
                 <div class={classes!("wfc-ds-grid")}>
                 {
-                    grid.iter().map(|row| {
-                        // "odd" is even-numbered index since they start at 0
-                        let is_odd = row_index % 2 == 0;
+                    grid.rows().map(|row| {
+                        // "odd" is even-numbered index since they start at 0. Those are the rows the topology leaves in place.
+                        let is_odd = !is_drawn_row_shifted(grid.get_topology(), row_index);
 
                         let row_class = classes!(
                             "wfc-ds-grid-row",
@@ -111,20 +246,18 @@ impl Component for WFCDungeonSandbox {
                             <div class={row_class}>
                             {
                                 row.iter().map(|cell| {
-                                    let cell_is_queued = queued_cell_locations.iter().any(|loc| {
-                                        loc.row == row_index && loc.col == col_index
-                                    });
-
                                     let location = GridLocation::new(row_index, col_index);
+                                    let cell_is_queued = self.generator.wfc.is_queued(&location);
+                                    let cell_has_problem = problem_locations.contains(&location);
 
                                     let set_cell = ctx.link().callback(move |_| Msg::SetCell(location));
 
                                     let ihtml = html! {
                                         <div class={classes!("wfc-ds-grid-cell-container")}>
-                                            <div class={classes!("wfc-ds-grid-cell-container-outer")}>
+                                            <div class={classes!("wfc-ds-grid-cell-container-outer", cell_has_problem.then_some("wfc-ds-grid-cell-problem"))}>
                                                 <div onclick={set_cell} class={classes!("wfc-ds-grid-cell-container-inner")}>
                                                     <DungeonCell ui_props={DungeonCellUIProps {
-                                                        possible_types: cell.borrow().possible_types.clone(),
+                                                        possible_types: grid.cell_types(cell),
                                                         is_start_location: location == self.generator.start_location,
                                                         is_goal_location: location == self.generator.goal_location,
                                                         is_goal_entrance_location: location == self.generator.goal_entrance_location,
@@ -160,34 +293,397 @@ impl Component for WFCDungeonSandbox {
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             Msg::None => {},
-            Msg::Reset => {
-                self.generator = new_generator();
-                self.generator.seed = self.seed_string.parse::<u64>().unwrap();
+            Msg::Reset | Msg::ApplySettings => {
+                match self.read_settings() {
+                    Err(message) => self.error_message = Some(message),
+                    Ok(config) => self.reset_generator(config),
+                }
+            },
+            Msg::Step => {
+                self.save_undo_snapshot();
+                let result = self.generator.step();
+                self.show_result(result);
             },
-            Msg::Step => self.generator.step(),
             Msg::StepComplete => {
                 let requeue = ctx.link().callback(|_: ()| Msg::StepComplete);
 
-                for _ in 0..if self.generator.state == DungeonGeneratorState::Fill { 20 } else { 1 } {
-                    self.generator.step();
+                self.save_undo_snapshot();
+
+                for _ in 0..self.generator.get_steps_per_frame() {
+                    let result = self.generator.step().and_then(|_| {
+                        if self.generator.can_do_more_work() { Ok(()) } else { self.generator.check_attempt() }
+                    });
+
+                    match result {
+                        Err(_) if self.generator.can_retry() => self.generator.start_attempt(self.generator.get_attempt() + 1),
+                        _ => if !self.show_result(result) {
+                            break;
+                        },
+                    }
                 }
 
-                if self.generator.can_do_more_work() {
+                if self.generator.can_do_more_work() && self.error_message.is_none() {
                     let timer = Timeout::new(1, move || {
                         requeue.emit(());
                     });
                     timer.forget();
                 }
             },
-            Msg::GenerateInstant => self.generator.generate(),
+            Msg::GenerateInstant => {
+                self.save_undo_snapshot();
+                let result = self.generator.generate();
+                self.show_result(result);
+            },
             Msg::SetPaint(cell_type) => self.selected_set_cell_type = cell_type,
-            Msg::SetCell(location) => self.generator.wfc.apply_types(vec![(location, vec![self.selected_set_cell_type])]),
-            Msg::SeedInputChanged(seed) => {
-                self.generator.seed = seed;
-                self.seed_string = seed.to_string();
+            Msg::ToggleObservationStrategy => {
+                self.generator.set_observation_strategy(match self.generator.observation_strategy {
+                    ObservationStrategy::Random => ObservationStrategy::MinEntropy,
+                    ObservationStrategy::MinEntropy => ObservationStrategy::Random,
+                });
+            },
+            Msg::SetCell(location) => {
+                self.save_undo_snapshot();
+                self.generator.apply_types(vec![(location, vec![self.selected_set_cell_type])]);
+            },
+            Msg::SettingChanged(setting, text) => {
+                self.setting_values.insert(setting, text);
+                self.check_settings();
+            },
+            Msg::CellWeightChanged(category, text) => {
+                if let Ok(weight) = text.parse::<f64>() {
+                    self.cell_weights.set_weight(category, weight);
+                    self.check_settings();
+                }
+            },
+            Msg::ResetCellWeights => {
+                self.cell_weights = CellProbabilityModel::default();
+                self.check_settings();
+            },
+            Msg::ClearNeighborWeights => {
+                self.cell_weights.neighbor_weights = None;
+                self.check_settings();
+            },
+            Msg::AddFitterExample => {
+                self.profile_status = match self.fitter.add_example(self.generator.wfc.get_grid()) {
+                    Ok(()) => format!("Added example {}", self.fitter.get_example_count()),
+                    Err(message) => message,
+                };
+            },
+            Msg::ClearFitterExamples => {
+                self.fitter = CellProbabilityFitter::new();
+                self.profile_status = String::from("Cleared the examples");
+            },
+            Msg::ToggleFitNeighbors => self.fit_neighbors = !self.fit_neighbors,
+            Msg::FitCellWeights => {
+                if let Some(cell_weights) = self.fitter.fit(self.fit_neighbors) {
+                    self.cell_weights = cell_weights;
+                    self.profile_status = format!("Fitted weights to {} examples", self.fitter.get_example_count());
+                    self.check_settings();
+                }
+            },
+            Msg::ExportProfile => {
+                self.settings_text = self.cell_weights.to_text();
+                self.profile_status = String::from("Exported the cell weights as a profile");
+            },
+            Msg::LoadProfile => {
+                match CellProbabilityModel::from_text(&self.settings_text) {
+                    Err(message) => self.profile_status = message,
+                    Ok(cell_weights) => {
+                        self.cell_weights = cell_weights;
+                        self.profile_status = String::from("Loaded the profile. Apply the settings to use it.");
+                        self.check_settings();
+                    },
+                }
+            },
+            Msg::ExportSettings => {
+                match self.read_settings() {
+                    Err(message) => self.settings_status = message,
+                    Ok(config) => self.settings_text = config.to_text(),
+                }
+            },
+            Msg::SettingsTextChanged(text) => self.settings_text = text,
+            Msg::LoadSettings => {
+                match DungeonGeneratorConfig::from_text(&self.settings_text) {
+                    Err(message) => self.settings_status = message,
+                    Ok(config) => {
+                        self.show_settings(&config);
+                        self.reset_generator(config);
+                    },
+                }
+            },
+            Msg::StepBack => {
+                if let Some(snapshot) = self.undo_history.pop_back() {
+                    self.generator.restore(&snapshot);
+                    self.error_message = None;
+                }
+            },
+            Msg::ToggleTrace => self.show_trace = !self.show_trace,
+            Msg::ExportReplay => {
+                let replay_log = self.generator.get_replay_log();
+                self.replay_status = match replay_log.final_grid_hash {
+                    Some(_) => String::from("Exported the finished dungeon"),
+                    None => String::from("Exported, but generation hasn't finished so the replay can't be checked"),
+                };
+                self.replay_text = replay_log.to_text();
+            },
+            Msg::ReplayTextChanged(text) => self.replay_text = text,
+            Msg::LoadReplay => {
+                match ReplayLog::from_text(&self.replay_text) {
+                    Err(message) => self.replay_status = message,
+                    Ok(replay_log) => {
+                        let player = ReplayPlayer::new(replay_log);
+                        match player.create_generator() {
+                            Err(error) => self.replay_status = error.to_string(),
+                            Ok(generator) => {
+                                self.generator = generator;
+                                self.generator.wfc.subscribe(self.event_log.clone());
+                                self.event_log.lock().unwrap().clear();
+                                self.undo_history.clear();
+                                self.error_message = None;
+                                self.show_settings(&player.get_log().config);
+                                self.replay_status = format!("Loaded {} actions", player.get_log().actions.len());
+                                self.replay_player = Some(player);
+                            },
+                        }
+                    },
+                }
+            },
+            Msg::ReplayStep => self.play_replay(1),
+            Msg::ReplayToEnd => {
+                let requeue = ctx.link().callback(|_: ()| Msg::ReplayToEnd);
+
+                self.play_replay(20);
+                if self.replay_player.as_ref().is_some_and(|player| !player.is_finished()) && self.error_message.is_none() {
+                    let timer = Timeout::new(1, move || {
+                        requeue.emit(());
+                    });
+                    timer.forget();
+                }
             },
         };
 
         true
     }
 }
+
+impl WFCDungeonSandbox {
+    /// Starts over with a fresh generator
+    fn reset_generator(&mut self, config: DungeonGeneratorConfig) {
+        self.error_message = None;
+        self.undo_history.clear();
+        self.replay_player = None;
+        self.event_log.lock().unwrap().clear();
+        self.generator = new_generator(config, &self.event_log);
+    }
+
+    /// Fills the settings panel in from a config
+    fn show_settings(&mut self, config: &DungeonGeneratorConfig) {
+        for setting in DungeonSetting::ALL.into_iter().filter(|setting| !setting.is_cell_weight()) {
+            self.setting_values.insert(setting, config.get_value(setting));
+        }
+
+        self.cell_weights = config.cell_weights.clone();
+        self.settings_status = String::from("");
+    }
+
+    /// Says what's wrong with the settings panel, if anything
+    fn check_settings(&mut self) {
+        self.settings_status = match self.read_settings() {
+            Ok(_) => String::from(""),
+            Err(message) => message,
+        };
+    }
+
+    /// Builds a config from what's typed into the settings panel
+    fn read_settings(&self) -> Result<DungeonGeneratorConfig, String> {
+        let mut config = DungeonGeneratorConfig::default();
+        for (setting, value) in self.setting_values.iter() {
+            config.set_value(*setting, value.trim())?;
+        }
+
+        config.cell_weights = self.cell_weights.clone();
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Remembers the generator as it is now, forgetting the oldest snapshot if the history is full
+    fn save_undo_snapshot(&mut self) {
+        if self.undo_history.len() == UNDO_HISTORY_LIMIT {
+            self.undo_history.pop_front();
+        }
+
+        self.undo_history.push_back(self.generator.snapshot());
+    }
+
+    /// Shows the error if generation failed. Returns whether it succeeded.
+    fn show_result(&mut self, result: Result<(), DungeonError>) -> bool {
+        match result {
+            Ok(()) => true,
+            Err(error) => {
+                self.error_message = Some(error.to_string());
+                false
+            },
+        }
+    }
+
+    /// Plays up to `count` replay actions, then says how the replay is going
+    fn play_replay(&mut self, count: usize) {
+        if let Some(player) = self.replay_player.as_mut() {
+            for _ in 0..count {
+                if let Err(error) = player.play_next(&mut self.generator) {
+                    self.error_message = Some(error.to_string());
+                    break;
+                }
+            }
+
+            self.replay_status = match player.verify(&self.generator) {
+                ReplayVerification::Matched => String::from("Replay finished and matched the recorded dungeon"),
+                ReplayVerification::Mismatched { expected, actual } => {
+                    format!("Replay finished but didn't match: expected hash {}, got {}", expected, actual)
+                },
+                ReplayVerification::NoHash => String::from("Replay finished. It was saved before generation finished, so there's nothing to check."),
+                ReplayVerification::Unfinished => format!("Played {} of {} actions", player.get_position(), player.get_log().actions.len()),
+            };
+        }
+    }
+
+    /// An input for every setting, which take effect when applied
+    fn view_settings(&self, ctx: &Context<Self>) -> Html {
+        let apply_settings = ctx.link().callback(|_| Msg::ApplySettings);
+        let export_settings = ctx.link().callback(|_| Msg::ExportSettings);
+        let load_settings = ctx.link().callback(|_| Msg::LoadSettings);
+        let settings_text_changed = {
+            let on_settings_text_changed = ctx.link().callback(|text: String| Msg::SettingsTextChanged(text));
+            Callback::from(move |e: InputEvent| {
+                let target: Option<EventTarget> = e.target();
+                let input = target.and_then(|t| t.dyn_into::<HtmlTextAreaElement>().ok());
+
+                if let Some(input) = input {
+                    on_settings_text_changed.emit(input.value());
+                }
+            })
+        };
+
+        let room_kind_labels = RoomKind::ALL.iter().map(|kind| kind.get_label()).collect::<Vec<&str>>().join(", ");
+
+        html! {
+            <div class={classes!("config-panel")}>
+                {
+                    DungeonSetting::ALL.into_iter().filter(|setting| !setting.is_cell_weight()).map(|setting| {
+                        let value = self.setting_values.get(&setting).cloned().unwrap_or_default();
+                        let on_change = on_value_changed(ctx.link().callback(move |text: String| Msg::SettingChanged(setting, text)));
+
+                        html! {
+                            <label class={classes!("wfc-ds-setting")}>
+                                { setting.get_label() }
+                                if setting == DungeonSetting::ObservationStrategy {
+                                    <select onchange={on_change}>
+                                        <option value={"MinEntropy"} selected={value == "MinEntropy"}>{"Min Entropy"}</option>
+                                        <option value={"Random"} selected={value == "Random"}>{"Random"}</option>
+                                    </select>
+                                } else if setting == DungeonSetting::RoomKindWeights {
+                                    <input type={"text"} title={room_kind_labels.clone()} value={value} onchange={on_change} />
+                                } else {
+                                    <input type={"number"} min={0} step={"any"} value={value} onchange={on_change} />
+                                }
+                            </label>
+                        }
+                    }).collect::<Html>()
+                }
+                <button disabled={!self.settings_status.is_empty()} onclick={apply_settings}>{"Apply Settings"}</button>
+                <button onclick={export_settings}>{"Export Settings"}</button>
+                <button onclick={load_settings}>{"Load Settings"}</button>
+                <div>{self.settings_status.clone()}</div>
+                <textarea rows={"4"} cols={"60"} value={self.settings_text.clone()} oninput={settings_text_changed} />
+                { self.view_cell_weights(ctx) }
+            </div>
+        }
+    }
+
+    /// A slider for each cell category's weight, next to a bar showing how often that category would be picked for a cell
+    /// that could still be anything. Categories with more cell types get more of the picks for the same weight.
+    fn view_cell_weights(&self, ctx: &Context<Self>) -> Html {
+        let reset_cell_weights = ctx.link().callback(|_| Msg::ResetCellWeights);
+        let clear_neighbor_weights = ctx.link().callback(|_| Msg::ClearNeighborWeights);
+        let add_fitter_example = ctx.link().callback(|_| Msg::AddFitterExample);
+        let clear_fitter_examples = ctx.link().callback(|_| Msg::ClearFitterExamples);
+        let toggle_fit_neighbors = ctx.link().callback(|_| Msg::ToggleFitNeighbors);
+        let fit_cell_weights = ctx.link().callback(|_| Msg::FitCellWeights);
+        let export_profile = ctx.link().callback(|_| Msg::ExportProfile);
+        let load_profile = ctx.link().callback(|_| Msg::LoadProfile);
+        let example_count = self.fitter.get_example_count();
+        let has_neighbor_weights = self.cell_weights.neighbor_weights.is_some();
+
+        html! {
+            <div class={classes!("wfc-ds-weights")}>
+                <div>{"Cell weights, and each category's share of picks for an open cell"}</div>
+                {
+                    self.cell_weights.distribution().into_iter().map(|(category, share)| {
+                        let weight_changed = {
+                            let on_weight_changed = ctx.link().callback(move |text: String| Msg::CellWeightChanged(category, text));
+                            Callback::from(move |e: InputEvent| {
+                                let target: Option<EventTarget> = e.target();
+                                let input = target.and_then(|t| t.dyn_into::<HtmlInputElement>().ok());
+
+                                if let Some(input) = input {
+                                    on_weight_changed.emit(input.value());
+                                }
+                            })
+                        };
+
+                        let weight = self.cell_weights.get_weight(category);
+
+                        html! {
+                            <div class={classes!("wfc-ds-weight")}>
+                                <span class={classes!("wfc-ds-weight-label")}>{category.get_label()}</span>
+                                <input type={"range"} min={"0"} max={"1"} step={"0.001"} value={weight.to_string()} oninput={weight_changed} />
+                                <span class={classes!("wfc-ds-weight-label")}>{format!("{:.3}", weight)}</span>
+                                <div class={classes!("wfc-ds-weight-bar")} style={format!("width: {:.2}em", share * WEIGHT_BAR_LENGTH)} />
+                                <span>{format!("{:.1}%", share * 100.0)}</span>
+                            </div>
+                        }
+                    }).collect::<Html>()
+                }
+                <button onclick={reset_cell_weights}>{"Default Weights"}</button>
+                <button disabled={!has_neighbor_weights} onclick={clear_neighbor_weights}>
+                    {format!("Neighbor Weights: {}", if has_neighbor_weights { "Clear" } else { "None" })}
+                </button>
+                <div>
+                    <button onclick={add_fitter_example}>{format!("Add Dungeon As Example ({})", example_count)}</button>
+                    <button disabled={example_count == 0} onclick={clear_fitter_examples}>{"Clear Examples"}</button>
+                    <button onclick={toggle_fit_neighbors}>{format!("Fit Neighbors: {}", if self.fit_neighbors { "On" } else { "Off" })}</button>
+                    <button disabled={example_count == 0} onclick={fit_cell_weights}>{"Fit Weights"}</button>
+                    <button onclick={export_profile}>{"Export Profile"}</button>
+                    <button onclick={load_profile}>{"Load Profile"}</button>
+                    <div>{self.profile_status.clone()}</div>
+                </div>
+            </div>
+        }
+    }
+
+    /// Lists the most recent events, newest first, under a count of everything seen so far
+    fn view_trace(&self) -> Html {
+        let event_log = self.event_log.lock().unwrap();
+
+        html! {
+            <div class={classes!("config-panel")}>
+                <div>{format!(
+                    "Enqueued: {} Reduced: {} Decisions: {} Contradictions: {} Backtracks: {}",
+                    event_log.enqueued_count,
+                    event_log.reduced_count,
+                    event_log.decision_count,
+                    event_log.contradiction_count,
+                    event_log.backtrack_count,
+                )}</div>
+                <div class={classes!("wfc-ds-trace")}>
+                {
+                    event_log.get_events().iter().rev().map(|event| html! {
+                        <div>{event.to_string()}</div>
+                    }).collect::<Html>()
+                }
+                </div>
+            </div>
+        }
+    }
+}
@@ -1,21 +1,45 @@
+use std::collections::VecDeque;
 use yew::prelude::*;
 use gloo_timers::callback::Timeout;
 use crate::wfc::*;
+use crate::wfc::topology::*;
+use crate::wfc::overlapping::*;
+use crate::wfc::hex::*;
+use crate::generation_fields::terrain::*;
 use crate::components::basic_hex_cell::*;
+use crate::wfc::random::*;
+use crate::views::is_drawn_row_shifted;
+
+const EXAMPLE_ROWS: usize = 10;
+const EXAMPLE_COLS: usize = 10;
+const OVERLAPPING_ROWS: usize = 30;
+const OVERLAPPING_COLS: usize = 30;
+const OVERLAPPING_BACKTRACK_LIMIT: usize = 50;
+/// How many steps back the undo history goes
+const UNDO_HISTORY_LIMIT: usize = 100;
 
 pub enum Msg {
     IterateQueue,
     IterateQueueComplete,
     IterateQueueCompleteInstant,
-    SetPaint(CellType),
+    SetPaint(TerrainCellType),
     PaintCell(GridLocation),
+    CycleWrapMode,
+    PaintExampleCell(GridLocation),
+    ToggleRotations,
+    LearnPatterns,
+    ObservePattern,
+    ObservePatternsComplete,
+    StepBack,
 }
 
-#[derive(Debug, Eq, Copy, Clone, PartialEq)]
-pub enum CellType {
-    Beach,
-    Sea,
-    Land,
+/// The color a settled terrain cell is drawn in
+pub fn terrain_color(cell_type: &TerrainCellType) -> &'static str {
+    match cell_type {
+        TerrainCellType::Beach => "tan",
+        TerrainCellType::Sea => "blue",
+        TerrainCellType::Land => "green",
+    }
 }
 
 #[derive(PartialEq, Properties)]
@@ -23,8 +47,41 @@ pub struct WFCSandboxProps {
 }
 
 pub struct WFCSandbox {
-    wfc: WaveFunctionCollapseContext<CellType>,
-    pub selected_paint_color: CellType,
+    wfc: WaveFunctionCollapseContext<TerrainCellType>,
+    /// Kept in the component so the reducer can hold on to its rules between messages
+    reducer: Box<dyn WaveFunctionReducer<TerrainCellType>>,
+    pub selected_paint_color: TerrainCellType,
+    /// Snapshots of the main grid from before each change, oldest first
+    undo_history: VecDeque<WfcSnapshot>,
+    /// A small hand-painted map for the overlapping model to learn from
+    example: HexGrid<TerrainCellType>,
+    pub include_rotations: bool,
+    overlapping_model: Option<OverlappingModel<TerrainCellType>>,
+    /// The map generated from the learned patterns. Its cells are pattern indices.
+    overlapping_wfc: Option<WaveFunctionCollapseContext<usize>>,
+    /// Why patterns couldn't be learned from the example, if they couldn't
+    pub overlapping_status: String,
+    random: SeededRandom,
+}
+
+/// Starts the example off as a lake with a beach around it, so there's something to learn before anything is painted
+fn new_example() -> HexGrid<TerrainCellType> {
+    let mut example = HexGrid::with_registry(EXAMPLE_ROWS, EXAMPLE_COLS, terrain_rules().get_shared_registry());
+    let center = CubeCoord::from_location(&GridLocation::new(EXAMPLE_ROWS as i64 / 2, EXAMPLE_COLS as i64 / 2));
+
+    for index in 0..EXAMPLE_ROWS * EXAMPLE_COLS {
+        let location = example.location_of(index);
+        let cell_type = match CubeCoord::from_location(&location).distance(&center) {
+            0..=1 => TerrainCellType::Sea,
+            2 => TerrainCellType::Beach,
+            _ => TerrainCellType::Land,
+        };
+
+        let types = example.get_registry().set_of(&[cell_type]);
+        example.get_cell_mut(&location).unwrap().possible_types = types;
+    }
+
+    example
 }
 
 impl Component for WFCSandbox {
@@ -32,11 +89,18 @@ impl Component for WFCSandbox {
     type Properties = WFCSandboxProps;
 
     fn create(_ctx: &Context<Self>) -> Self {
-        let all_types = vec![CellType::Beach, CellType::Land, CellType::Sea];
-
         WFCSandbox {
-            wfc: WaveFunctionCollapseContext::<CellType>::new(40, 40, &all_types),
-            selected_paint_color: CellType::Land,
+            // The grid has to index types the same way the rules do
+            wfc: WaveFunctionCollapseContext::<TerrainCellType>::with_registry(40, 40, terrain_rules().get_shared_registry()),
+            reducer: terrain_reducer(),
+            selected_paint_color: TerrainCellType::Land,
+            undo_history: VecDeque::new(),
+            example: new_example(),
+            include_rotations: true,
+            overlapping_model: None,
+            overlapping_wfc: None,
+            overlapping_status: String::from(""),
+            random: SeededRandom::new(0),
         }
     }
 
@@ -44,36 +108,49 @@ impl Component for WFCSandbox {
         let iterate_queue = ctx.link().callback(|_| Msg::IterateQueue);
         let iterate_queue_complete = ctx.link().callback(|_| Msg::IterateQueueComplete);
         let iterate_queue_complete_instant = ctx.link().callback(|_| Msg::IterateQueueCompleteInstant);
-        let select_sea = ctx.link().callback(|_| Msg::SetPaint(CellType::Sea));
-        let select_land = ctx.link().callback(|_| Msg::SetPaint(CellType::Land));
-
-        let grid = &self.wfc.get_grid().grid;
-
-        let paint_color = match self.selected_paint_color {
-            CellType::Beach => "tan",
-            CellType::Sea => "blue",
-            CellType::Land => "green",
+        let select_beach = ctx.link().callback(|_| Msg::SetPaint(TerrainCellType::Beach));
+        let select_sea = ctx.link().callback(|_| Msg::SetPaint(TerrainCellType::Sea));
+        let select_land = ctx.link().callback(|_| Msg::SetPaint(TerrainCellType::Land));
+        let cycle_wrap_mode = ctx.link().callback(|_| Msg::CycleWrapMode);
+        let step_back = ctx.link().callback(|_| Msg::StepBack);
+        let toggle_rotations = ctx.link().callback(|_| Msg::ToggleRotations);
+        let learn_patterns = ctx.link().callback(|_| Msg::LearnPatterns);
+        let observe_pattern = ctx.link().callback(|_| Msg::ObservePattern);
+        let observe_patterns_complete = ctx.link().callback(|_| Msg::ObservePatternsComplete);
+
+        let grid = self.wfc.get_grid();
+
+        let paint_color = terrain_color(&self.selected_paint_color);
+
+        let wrap_mode_name = match grid.get_wrap_mode() {
+            WrapMode::None => "Off",
+            WrapMode::Horizontal => "Horizontal",
+            WrapMode::Vertical => "Vertical",
+            WrapMode::Both => "Both",
         };
 
-        let queued_cell_locations = self.wfc.get_queue();
-        let queue_is_empty = queued_cell_locations.is_empty();
+        let queue_is_empty = self.wfc.get_queue().is_empty();
+        let overlapping_can_observe = self.overlapping_wfc.as_ref().is_some_and(|wfc| !wfc.is_settled());
         let mut row_index = 0;
 
         html! {
             <div>
                 <div class={classes!("config-panel")}>
                     <div style={format!("background-color: {}", paint_color)}>{crate::util::HTML_NBSP}</div>
+                    <button onclick={select_beach}>{"Beach"}</button>
                     <button onclick={select_sea}>{"Sea"}</button>
                     <button onclick={select_land}>{"Land"}</button>
                     <button disabled={queue_is_empty} onclick={iterate_queue}>{"Iterate Queue"}</button>
                     <button disabled={queue_is_empty} onclick={iterate_queue_complete}>{"Iterate To End"}</button>
                     <button disabled={queue_is_empty} onclick={iterate_queue_complete_instant}>{"Iterate To End (Instant)"}</button>
+                    <button onclick={cycle_wrap_mode}>{format!("Wrap: {}", wrap_mode_name)}</button>
+                    <button disabled={self.undo_history.is_empty()} onclick={step_back}>{format!("Step Back ({})", self.undo_history.len())}</button>
                 </div>
                 <div class={classes!("wfc-sandbox-hex-grid")}>
                 {
-                    grid.iter().map(|row| {
-                        // "odd" is even-numbered index since they start at 0
-                        let is_odd = row_index % 2 == 0;
+                    grid.rows().map(|row| {
+                        // "odd" is even-numbered index since they start at 0. Those are the rows the topology leaves in place.
+                        let is_odd = !is_drawn_row_shifted(grid.get_topology(), row_index);
 
                         let row_class = classes!(
                             "wfc-sandbox-grid-row",
@@ -87,20 +164,13 @@ impl Component for WFCSandbox {
                             {
                                 row.iter().map(|cell| {
                                     let mut color = "purple";
-                                    let cell = cell.borrow();
-                                    if cell.possible_types.len() == 0 {
+                                    if cell.possible_types.count() == 0 {
                                         color = "black";
-                                    } else if cell.possible_types.len() == 1 {
-                                        color = match cell.possible_types[0] {
-                                            CellType::Beach => "tan",
-                                            CellType::Sea => "blue",
-                                            CellType::Land => "green",
-                                        }
+                                    } else if cell.possible_types.count() == 1 {
+                                        color = terrain_color(self.wfc.get_grid().get_registry().get(cell.possible_types.first().unwrap()));
                                     }
 
-                                    let cell_is_queued = queued_cell_locations.iter().any(|loc| {
-                                        loc.row == row_index && loc.col == col_index
-                                    });
+                                    let cell_is_queued = self.wfc.is_queued(&GridLocation::new(row_index, col_index));
 
 
                                     let paint_cell = ctx.link().callback(move |_| Msg::PaintCell(GridLocation::new(row_index, col_index)));
@@ -134,75 +204,37 @@ impl Component for WFCSandbox {
                     }).collect::<Html>()
                 }
                 </div>
+                <div class={classes!("config-panel")}>
+                    <div>{"Overlapping Model Example"}</div>
+                    <button onclick={toggle_rotations}>{format!("Rotations: {}", if self.include_rotations { "On" } else { "Off" })}</button>
+                    <button onclick={learn_patterns}>{"Learn Patterns"}</button>
+                    <button disabled={!overlapping_can_observe} onclick={observe_pattern}>{"Observe"}</button>
+                    <button disabled={!overlapping_can_observe} onclick={observe_patterns_complete}>{"Observe To End"}</button>
+                    if let Some(model) = &self.overlapping_model {
+                        <div>{format!("Patterns: {}", model.get_patterns().len())}</div>
+                    }
+                    <div>{self.overlapping_status.clone()}</div>
+                </div>
+                { self.view_small_grid(ctx, &self.example, |cell| cell.settled_type().map(|index| *self.example.get_registry().get(index)), Some(Msg::PaintExampleCell)) }
+                if let (Some(model), Some(wfc)) = (&self.overlapping_model, &self.overlapping_wfc) {
+                    { self.view_small_grid(ctx, wfc.get_grid(), |cell| model.cell_type(cell).copied(), None) }
+                }
             </div>
         }
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
-        let rules: WafeFunctionReducer<CellType> = |loc, cell, grid| {
-            let mut cell = cell.borrow_mut();
-            let mut changed = false;
-
-            // We're attempting go to a basic land-sea-beach thing here.
-            // Land can only touch land and beach
-            // Sea can only touch sea and beach
-            // Beach can touch anything, unless surrounded by land (surrounded by sea can be a small island, sand bar, etc.)
-
-            let cell2 = cell.clone();
-            let cell_can_be = move |cell_type: CellType| cell2.possible_types.contains(&cell_type);
-
-            let neighbors: Vec<MaybeCell<CellType>> = loc.get_neighbors().iter().map(|location| grid.get_cell(location)).collect();
-
-            let cell_has_neighbor_of_type = |cell_type: CellType| {
-                neighbors.iter().any(|neighbor| match neighbor { None => false, Some(neighbor) => {
-                    let neighbor = neighbor.borrow();
-                    neighbor.possible_types.len() == 1 && neighbor.possible_types.contains(&cell_type)
-                }} )
-            };
-
-            let all_neighbors_are = |cell_type: CellType| {
-                neighbors.iter().all(|neighbor| match neighbor { None => false, Some(neighbor) => {
-                    let neighbor = neighbor.borrow();
-                    neighbor.possible_types.len() == 1 && neighbor.possible_types.contains(&cell_type)
-                }} )
-            };
-
-            if cell_can_be(CellType::Land) && cell_has_neighbor_of_type(CellType::Sea) {
-                cell.possible_types = cell.possible_types.clone().into_iter().filter(|t| *t != CellType::Land).collect();
-                changed = true;
-            }
-
-            if cell_can_be(CellType::Sea) && cell_has_neighbor_of_type(CellType::Land) {
-                cell.possible_types = cell.possible_types.clone().into_iter().filter(|t| *t != CellType::Sea).collect();
-                changed = true;
-            }
-
-            if cell_can_be(CellType::Beach) && all_neighbors_are(CellType::Land) {
-                cell.possible_types = vec![CellType::Land];
-                changed = true;
-            }
-
-            // This might not technically belong here...re-evaluate for non-toy application
-
-            // We generally want land tiles to generate more land and sea tiles to generate more sea.
-            // If we were just narrowed down, possibly just keep building on that.
-            if changed && cell.possible_types.len() == 2 {
-                if cell_can_be(CellType::Land) && cell_has_neighbor_of_type(CellType::Land) {
-                    cell.possible_types = vec![CellType::Land];
-                } else if cell_can_be(CellType::Sea) && cell_has_neighbor_of_type(CellType::Sea) {
-                    cell.possible_types = vec![CellType::Sea];
-                }
-            }
-
-            changed
-        };
-
         match msg {
-            Msg::IterateQueue => self.wfc.iterate_queue(rules),
+            Msg::IterateQueue => {
+                self.save_undo_snapshot();
+                self.wfc.iterate_queue(self.reducer.as_mut());
+            },
             Msg::IterateQueueComplete => {
                 let requeue = ctx.link().callback(|_: ()| Msg::IterateQueueComplete);
 
-                self.wfc.iterate_queue(rules);
+                self.save_undo_snapshot();
+
+                self.wfc.iterate_queue(self.reducer.as_mut());
                 if !self.wfc.get_queue().is_empty() {
                     let timer = Timeout::new(1, move || {
                         requeue.emit(());
@@ -210,11 +242,134 @@ impl Component for WFCSandbox {
                     timer.forget();
                 }
             },
-            Msg::IterateQueueCompleteInstant => self.wfc.iterate_queue_complete(rules),
+            Msg::IterateQueueCompleteInstant => {
+                self.save_undo_snapshot();
+                self.wfc.iterate_queue_complete(self.reducer.as_mut());
+            },
             Msg::SetPaint(cell_type) => self.selected_paint_color = cell_type,
-            Msg::PaintCell(location) => self.wfc.apply_types(vec![(location, vec![self.selected_paint_color])]),
+            Msg::PaintCell(location) => {
+                self.save_undo_snapshot();
+                self.wfc.apply_types(vec![(location, vec![self.selected_paint_color])]);
+            },
+            Msg::PaintExampleCell(location) => {
+                let types = self.example.get_registry().set_of(&[self.selected_paint_color]);
+                self.example.get_cell_mut(&location).unwrap().possible_types = types;
+            },
+            Msg::ToggleRotations => self.include_rotations = !self.include_rotations,
+            Msg::LearnPatterns => {
+                match OverlappingModel::from_example(&self.example, 1, self.include_rotations) {
+                    Err(message) => self.overlapping_status = message,
+                    Ok(model) => {
+                        let mut wfc = model.new_context(OVERLAPPING_ROWS, OVERLAPPING_COLS);
+                        wfc.set_backtrack_limit(OVERLAPPING_BACKTRACK_LIMIT);
+
+                        self.overlapping_wfc = Some(wfc);
+                        self.overlapping_model = Some(model);
+                        self.overlapping_status = String::from("");
+                    },
+                }
+            },
+            Msg::ObservePattern => { self.observe_pattern(); },
+            Msg::ObservePatternsComplete => {
+                let requeue = ctx.link().callback(|_: ()| Msg::ObservePatternsComplete);
+
+                if self.observe_pattern() {
+                    let timer = Timeout::new(1, move || {
+                        requeue.emit(());
+                    });
+                    timer.forget();
+                }
+            },
+            Msg::StepBack => {
+                if let Some(snapshot) = self.undo_history.pop_back() {
+                    self.wfc.restore(&snapshot);
+                }
+            },
+            Msg::CycleWrapMode => {
+                // Wrapping makes the terrain tile seamlessly. The grid is 40x40, so the hex rows line up across the seam.
+                let next_wrap_mode = match self.wfc.get_grid().get_wrap_mode() {
+                    WrapMode::None => WrapMode::Horizontal,
+                    WrapMode::Horizontal => WrapMode::Vertical,
+                    WrapMode::Vertical => WrapMode::Both,
+                    WrapMode::Both => WrapMode::None,
+                };
+
+                self.save_undo_snapshot();
+                self.wfc.set_wrap_mode(next_wrap_mode).expect("A 40x40 grid can wrap any way");
+            },
         };
 
         true
     }
 }
+
+impl WFCSandbox {
+    /// Remembers the main grid as it is now, forgetting the oldest snapshot if the history is full
+    fn save_undo_snapshot(&mut self) {
+        if self.undo_history.len() == UNDO_HISTORY_LIMIT {
+            self.undo_history.pop_front();
+        }
+
+        self.undo_history.push_back(self.wfc.snapshot());
+    }
+
+    /// Collapses one more cell of the overlapping model's map. Returns false if there was nothing left to do.
+    fn observe_pattern(&mut self) -> bool {
+        let random = &mut self.random;
+        match (&self.overlapping_model, &mut self.overlapping_wfc) {
+            (Some(model), Some(wfc)) => model.observe(wfc, &mut || random.next_f64()),
+            _ => false,
+        }
+    }
+
+    /// Draws a grid that isn't the main one. Cells are colored by whatever terrain type they stand for, if known yet.
+    fn view_small_grid<TCellType: Clone + PartialEq>(
+        &self,
+        ctx: &Context<Self>,
+        grid: &HexGrid<TCellType>,
+        terrain_type: impl Fn(&PendingCell) -> Option<TerrainCellType>,
+        on_click: Option<fn(GridLocation) -> Msg>,
+    ) -> Html {
+        html! {
+            <div class={classes!("wfc-sandbox-hex-grid")}>
+            {
+                grid.rows().enumerate().map(|(row_index, row)| {
+                    let is_odd = !is_drawn_row_shifted(grid.get_topology(), row_index as i64);
+
+                    let row_class = classes!(
+                        "wfc-sandbox-grid-row",
+                        if is_odd { "wfc-sandbox-grid-row-odd" } else { "wfc-sandbox-grid-row-even" }
+                    );
+
+                    html! {
+                        <div class={row_class}>
+                        {
+                            row.iter().map(|cell| {
+                                let color = match terrain_type(cell) {
+                                    Some(cell_type) => terrain_color(&cell_type),
+                                    None if cell.possible_types.is_empty() => "black",
+                                    None => "purple",
+                                };
+
+                                let location = cell.location;
+                                let click = on_click.map(|on_click| ctx.link().callback(move |_| on_click(location)));
+
+                                html! {
+                                    <div class={classes!("wfc-sandbox-grid-cell-container")}>
+                                        <div class={classes!("wfc-sandbox-grid-cell-container-outer")}>
+                                            <div onclick={click} class={classes!("wfc-sandbox-grid-cell-container-inner")}>
+                                                <BasicHexCell color={color} />
+                                            </div>
+                                        </div>
+                                    </div>
+                                }
+                            }).collect::<Html>()
+                        }
+                        </div>
+                    }
+                }).collect::<Html>()
+            }
+            </div>
+        }
+    }
+}
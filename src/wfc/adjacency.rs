@@ -0,0 +1,155 @@
+use std::sync::Arc;
+use crate::wfc::*;
+use crate::wfc::topology::*;
+
+/// A data-driven rule set listing which cell types may sit next to each other in each neighbor direction.
+/// Directions are those of the topology the rules are made for, hex by default.
+/// Rules are symmetric: allowing A to the right of B also allows B to the left of A.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AdjacencyRules<TCellType: Clone, TTopology: Topology = HexTopology> {
+    /// The topology the rules are made for, which decides the directions and their opposites
+    topology: TTopology,
+    /// Every type the rules know about. This must match the registry of any grid the rules are used on.
+    registry: Arc<TypeRegistry<TCellType>>,
+    /// allowed[type][direction] is the set of types that may be the neighbor in that direction
+    allowed: Vec<Vec<TypeSet>>,
+    /// edge_allowed[direction] is the set of types that may have the edge of the grid in that direction
+    edge_allowed: Vec<TypeSet>,
+}
+
+impl<TCellType: Clone + PartialEq> AdjacencyRules<TCellType> {
+    /// Creates a rule set for the default hex grid where nothing may touch, but anything may sit at the edge of the grid
+    pub fn new(types: &[TCellType]) -> AdjacencyRules<TCellType> {
+        AdjacencyRules::with_topology(types, HexTopology::default())
+    }
+}
+
+impl<TCellType: Clone + PartialEq, TTopology: Topology> AdjacencyRules<TCellType, TTopology> {
+    /// Creates a rule set for the given topology where nothing may touch, but anything may sit at the edge of the grid
+    pub fn with_topology(types: &[TCellType], topology: TTopology) -> AdjacencyRules<TCellType, TTopology> {
+        AdjacencyRules::with_registry(Arc::new(TypeRegistry::new(types)), topology)
+    }
+
+    /// Creates a rule set over an existing registry, so grids sharing it can be checked against the rules cheaply
+    pub fn with_registry(registry: Arc<TypeRegistry<TCellType>>, topology: TTopology) -> AdjacencyRules<TCellType, TTopology> {
+        let direction_count = topology.direction_count();
+
+        AdjacencyRules {
+            allowed: registry.types().iter().map(|_| vec![registry.none(); direction_count]).collect(),
+            edge_allowed: vec![registry.all(); direction_count],
+            registry,
+            topology,
+        }
+    }
+
+    /// Allows neighbor_type to be the neighbor of cell_type in the given direction, and the reverse
+    pub fn allow(&mut self, cell_type: &TCellType, direction: usize, neighbor_type: &TCellType) {
+        let cell_index = self.index_of(cell_type);
+        let neighbor_index = self.index_of(neighbor_type);
+        self.allow_index(cell_index, direction, neighbor_index);
+    }
+
+    /// Same as allow, but with registry indices. Handy for big generated rule sets, where looking types up adds up.
+    pub fn allow_index(&mut self, cell_index: usize, direction: usize, neighbor_index: usize) {
+        self.allowed[cell_index][direction].insert(neighbor_index);
+        self.allowed[neighbor_index][self.topology.opposite_direction(direction)].insert(cell_index);
+    }
+
+    /// Allows the two types to touch on any side
+    pub fn allow_all_directions(&mut self, cell_type: &TCellType, neighbor_type: &TCellType) {
+        for direction in 0..self.topology.direction_count() {
+            self.allow(cell_type, direction, neighbor_type);
+        }
+    }
+
+    /// Stops cell_type from having the edge of the grid in the given direction
+    pub fn forbid_edge(&mut self, cell_type: &TCellType, direction: usize) {
+        let cell_index = self.index_of(cell_type);
+        self.edge_allowed[direction].remove(cell_index);
+    }
+
+    /// Whether neighbor_type may be the neighbor of cell_type in the given direction
+    pub fn is_allowed(&self, cell_type: &TCellType, direction: usize, neighbor_type: &TCellType) -> bool {
+        self.allowed[self.index_of(cell_type)][direction].contains(self.index_of(neighbor_type))
+    }
+
+    /// The registry the rules index types with
+    pub fn get_registry(&self) -> &TypeRegistry<TCellType> {
+        &self.registry
+    }
+
+    /// Gets a shared handle to the registry, for building grids the rules can be used on
+    pub fn get_shared_registry(&self) -> Arc<TypeRegistry<TCellType>> {
+        self.registry.clone()
+    }
+
+    /// Removes every type from the cell that has no allowed option left in one of its neighbors.
+    /// Panics if the grid's registry isn't the rules' one, since the same index would stand for different types.
+    pub fn reduce(&self, loc: &GridLocation, cell: &PendingCell, grid: &HexGrid<TCellType, TTopology>) -> Option<TypeSet> {
+        if !grid.has_registry(&self.registry) {
+            panic!("Adjacency rules were used on a grid with a different type registry");
+        }
+
+        let mut remaining_types = cell.possible_types.clone();
+        let initial_option_count = remaining_types.count();
+
+        for (direction, neighbor_location) in grid.get_neighbors(loc).iter().enumerate() {
+            match grid.get_cell(neighbor_location) {
+                None => remaining_types.intersect_with(&self.edge_allowed[direction]),
+                Some(neighbor) => {
+                    // Anything the neighbor could be allows some set of types back in our direction
+                    let opposite = self.topology.opposite_direction(direction);
+                    let mut supported_types = self.registry.none();
+                    for neighbor_type in neighbor.possible_types.iter() {
+                        supported_types.union_with(&self.allowed[neighbor_type][opposite]);
+                    }
+
+                    remaining_types.intersect_with(&supported_types);
+                },
+            }
+        }
+
+        if remaining_types.count() == initial_option_count {
+            None
+        } else {
+            Some(remaining_types)
+        }
+    }
+
+    fn index_of(&self, cell_type: &TCellType) -> usize {
+        self.registry.index_of(cell_type).expect("Cell type is not part of the adjacency rules")
+    }
+}
+
+impl<TCellType: Clone + PartialEq, TTopology: Topology> WaveFunctionReducer<TCellType, TTopology> for AdjacencyRules<TCellType, TTopology> {
+    fn reduce(&mut self, loc: &GridLocation, cell: &PendingCell, grid: &HexGrid<TCellType, TTopology>) -> Option<TypeSet> {
+        AdjacencyRules::reduce(self, loc, cell, grid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_work_on_a_grid_with_an_equal_registry() {
+        let mut rules = AdjacencyRules::new(&[0usize, 1]);
+        rules.allow_all_directions(&0, &0);
+        let mut wfc = WaveFunctionCollapseContext::with_registry(1, 2, Arc::new(TypeRegistry::new(&[0usize, 1])));
+        wfc.set_cell_types(&GridLocation::new(0, 1), TypeSet::single(2, 0));
+
+        let grid = wfc.get_grid();
+        let location = GridLocation::new(0, 0);
+        assert_eq!(rules.reduce(&location, grid.get_cell(&location).unwrap(), grid), Some(TypeSet::single(2, 0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "different type registry")]
+    fn rules_refuse_a_grid_with_a_different_registry() {
+        let rules = AdjacencyRules::new(&[0usize, 1]);
+        let grid = HexGrid::new(1, 2, &[1usize, 0]);
+
+        let location = GridLocation::new(0, 0);
+        rules.reduce(&location, grid.get_cell(&location).unwrap(), &grid);
+    }
+}
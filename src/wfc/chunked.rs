@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::wfc::*;
+use crate::wfc::topology::*;
+use crate::wfc::random::*;
+
+/// Which chunk of an infinite world something is in, counted in chunks rather than cells
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct ChunkLocation {
+    pub row: i64,
+    pub col: i64,
+}
+
+impl ChunkLocation {
+    pub fn new(row: i64, col: i64) -> ChunkLocation {
+        ChunkLocation {
+            row,
+            col,
+        }
+    }
+}
+
+/// A generated piece of an infinite world.
+/// The chunk's top row and left column are the seams it shares with the chunks above and to the left of it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct WorldChunk {
+    pub location: ChunkLocation,
+    rows: usize,
+    cols: usize,
+    /// The settled types of each cell, row by row
+    cells: Vec<TypeSet>,
+}
+
+impl WorldChunk {
+    /// Gets the types of a cell by its location within the chunk
+    pub fn get_types(&self, local_location: &GridLocation) -> Option<&TypeSet> {
+        if local_location.row < 0 || local_location.col < 0 || local_location.row >= self.rows as i64 || local_location.col >= self.cols as i64 {
+            None
+        } else {
+            Some(&self.cells[local_location.row as usize * self.cols + local_location.col as usize])
+        }
+    }
+}
+
+/// The pieces of the world that get generated separately. Each one gets its own random stream so the order things are
+/// generated in doesn't matter.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum WorldPart {
+    /// The cells around the top left of a chunk, where four chunks meet
+    Corner = 1,
+    /// A chunk's top row, running between two corners
+    TopSeam = 2,
+    /// A chunk's left column, running between two corners
+    LeftSeam = 3,
+    /// Everything inside a chunk's seams
+    Interior = 4,
+}
+
+/// The types picked around a corner where four chunks meet: the corner cell itself, and the first cell of each seam
+/// leading away from it. Cells at the start of two different seams can touch, so they're all picked together.
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct CornerCells {
+    center: TypeSet,
+    up: TypeSet,
+    down: TypeSet,
+    left: TypeSet,
+    right: TypeSet,
+}
+
+/// An endless grid, generated a chunk at a time as it's asked for.
+/// Chunks don't overlap: each owns its top row and left column, which are seams shared with its neighbors. Corners are
+/// picked first, then each seam is collapsed between its two corners, then a chunk's interior is collapsed inside its
+/// four seams. Every step only depends on the world seed and where it is, so a chunk always comes out the same no matter
+/// which chunks were generated before it, and chunks can be dropped and regenerated freely.
+#[derive(Debug, PartialEq, Clone)]
+pub struct InfiniteWorld<TCellType: Clone, TTopology: Topology = HexTopology> {
+    seed: u64,
+    chunk_rows: usize,
+    chunk_cols: usize,
+    registry: Arc<TypeRegistry<TCellType>>,
+    topology: TTopology,
+    /// Relative likelihood of each type, indexed the same as the registry
+    weights: Vec<f64>,
+    backtrack_limit: usize,
+    chunks: HashMap<ChunkLocation, WorldChunk>,
+    /// Each corner and seam is used by several chunks, so it's kept rather than collapsed again
+    corners: HashMap<ChunkLocation, CornerCells>,
+    top_seams: HashMap<ChunkLocation, Vec<TypeSet>>,
+    left_seams: HashMap<ChunkLocation, Vec<TypeSet>>,
+}
+
+impl<TCellType: Clone + PartialEq> InfiniteWorld<TCellType> {
+    /// Creates a world of pointy top hexes. Nothing is generated until chunks are asked for. Errors if the chunk size won't work.
+    pub fn new(seed: u64, chunk_rows: usize, chunk_cols: usize, registry: Arc<TypeRegistry<TCellType>>, weight: &dyn Fn(&TCellType) -> f64) -> Result<InfiniteWorld<TCellType>, String> {
+        InfiniteWorld::with_topology(seed, chunk_rows, chunk_cols, registry, weight, HexTopology::default())
+    }
+}
+
+impl<TCellType: Clone + PartialEq, TTopology: Topology> InfiniteWorld<TCellType, TTopology> {
+    /// Creates a world with the given topology.
+    /// Errors if chunks are too small to have an interior, or their size would break the topology's pattern from one chunk to the next.
+    pub fn with_topology(
+        seed: u64,
+        chunk_rows: usize,
+        chunk_cols: usize,
+        registry: Arc<TypeRegistry<TCellType>>,
+        weight: &dyn Fn(&TCellType) -> f64,
+        topology: TTopology,
+    ) -> Result<InfiniteWorld<TCellType, TTopology>, String> {
+        // Seams start and end with cells picked around their corners, and those need some room between them
+        if chunk_rows < 4 || chunk_cols < 4 {
+            return Err(String::from("Chunks need at least 4 rows and columns"));
+        }
+
+        if !chunk_rows.is_multiple_of(topology.row_period()) || !chunk_cols.is_multiple_of(topology.col_period()) {
+            return Err(format!("Chunk size must be a multiple of {} rows and {} columns on this topology", topology.row_period(), topology.col_period()));
+        }
+
+        Ok(InfiniteWorld {
+            seed,
+            chunk_rows,
+            chunk_cols,
+            weights: registry.types().iter().map(|cell_type| weight(cell_type).max(0.0)).collect(),
+            registry,
+            topology,
+            backtrack_limit: 0,
+            chunks: HashMap::new(),
+            corners: HashMap::new(),
+            top_seams: HashMap::new(),
+            left_seams: HashMap::new(),
+        })
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn get_chunk_rows(&self) -> usize {
+        self.chunk_rows
+    }
+
+    pub fn get_chunk_cols(&self) -> usize {
+        self.chunk_cols
+    }
+
+    pub fn get_registry(&self) -> &TypeRegistry<TCellType> {
+        &self.registry
+    }
+
+    pub fn get_topology(&self) -> &TTopology {
+        &self.topology
+    }
+
+    /// Sets the backtrack limit used while collapsing each seam and chunk. See WaveFunctionCollapseContext::set_backtrack_limit.
+    pub fn set_backtrack_limit(&mut self, limit: usize) {
+        self.backtrack_limit = limit;
+    }
+
+    /// Gets the chunk a world location is in, along with its location inside that chunk
+    pub fn chunk_location_of(&self, loc: &GridLocation) -> (ChunkLocation, GridLocation) {
+        (
+            ChunkLocation::new(loc.row.div_euclid(self.chunk_rows as i64), loc.col.div_euclid(self.chunk_cols as i64)),
+            GridLocation::new(loc.row.rem_euclid(self.chunk_rows as i64), loc.col.rem_euclid(self.chunk_cols as i64)),
+        )
+    }
+
+    /// Gets the world location of a chunk's top left cell
+    pub fn chunk_origin(&self, chunk: &ChunkLocation) -> GridLocation {
+        GridLocation::new(chunk.row * self.chunk_rows as i64, chunk.col * self.chunk_cols as i64)
+    }
+
+    /// Gets a chunk if it's been generated
+    pub fn get_chunk(&self, chunk: &ChunkLocation) -> Option<&WorldChunk> {
+        self.chunks.get(chunk)
+    }
+
+    /// Gets the types of the cell at a world location, if its chunk has been generated
+    pub fn get_types(&self, loc: &GridLocation) -> Option<&TypeSet> {
+        let (chunk, local_location) = self.chunk_location_of(loc);
+        self.chunks.get(&chunk).and_then(|chunk| chunk.get_types(&local_location))
+    }
+
+    /// Gets a chunk, generating it first if needed
+    pub fn generate_chunk<TReducer>(&mut self, chunk: &ChunkLocation, reduce_types: &mut TReducer) -> &WorldChunk
+        where TReducer: WaveFunctionReducer<TCellType, TTopology> + ?Sized
+    {
+        if !self.chunks.contains_key(chunk) {
+            let generated_chunk = self.collapse_interior(chunk, reduce_types);
+            self.chunks.insert(*chunk, generated_chunk);
+        }
+
+        &self.chunks[chunk]
+    }
+
+    /// Generates every chunk touching the rectangle of world locations between the two corners, inclusive
+    pub fn generate_area<TReducer>(&mut self, top_left: &GridLocation, bottom_right: &GridLocation, reduce_types: &mut TReducer)
+        where TReducer: WaveFunctionReducer<TCellType, TTopology> + ?Sized
+    {
+        let (first_chunk, _) = self.chunk_location_of(top_left);
+        let (last_chunk, _) = self.chunk_location_of(bottom_right);
+        for row in first_chunk.row..=last_chunk.row {
+            for col in first_chunk.col..=last_chunk.col {
+                self.generate_chunk(&ChunkLocation::new(row, col), reduce_types);
+            }
+        }
+    }
+
+    /// Drops generated chunks, and the corners and seams they used, outside the rectangle of chunks between the two corners.
+    /// Since chunks only depend on the seed and their location, dropped chunks come back the same if they're generated again.
+    pub fn unload_outside(&mut self, first_chunk: &ChunkLocation, last_chunk: &ChunkLocation) {
+        let is_inside = |chunk: &ChunkLocation| {
+            chunk.row >= first_chunk.row && chunk.row <= last_chunk.row && chunk.col >= first_chunk.col && chunk.col <= last_chunk.col
+        };
+
+        self.chunks.retain(|chunk, _| is_inside(chunk));
+
+        // Chunks use the seams of the chunks below and right of them too
+        let is_seam_inside = |chunk: &ChunkLocation| {
+            chunk.row >= first_chunk.row && chunk.row <= last_chunk.row + 1 && chunk.col >= first_chunk.col && chunk.col <= last_chunk.col + 1
+        };
+
+        self.corners.retain(|chunk, _| is_seam_inside(chunk));
+        self.top_seams.retain(|chunk, _| is_seam_inside(chunk));
+        self.left_seams.retain(|chunk, _| is_seam_inside(chunk));
+    }
+
+    /// Collapses the cells around the corner at a chunk's top left
+    fn corner<TReducer>(&mut self, chunk: &ChunkLocation, reduce_types: &mut TReducer) -> CornerCells
+        where TReducer: WaveFunctionReducer<TCellType, TTopology> + ?Sized
+    {
+        if let Some(corner) = self.corners.get(chunk) {
+            return corner.clone();
+        }
+
+        // Starting a whole period up and left of the corner keeps the small grid lined up with the world's pattern
+        let (row_period, col_period) = (self.topology.row_period(), self.topology.col_period());
+        let (rows, cols) = (row_period + 2, col_period + 2);
+        let random = self.part_random(WorldPart::Corner, chunk);
+        let cells = self.collapse(rows, cols, vec![], random, reduce_types);
+
+        let types_at = |row: usize, col: usize| cells[row * cols + col].possible_types.clone();
+        let corner = CornerCells {
+            center: types_at(row_period, col_period),
+            up: types_at(row_period - 1, col_period),
+            down: types_at(row_period + 1, col_period),
+            left: types_at(row_period, col_period - 1),
+            right: types_at(row_period, col_period + 1),
+        };
+
+        self.corners.insert(*chunk, corner.clone());
+        corner
+    }
+
+    /// Collapses a chunk's top row between the corners at either end
+    fn top_seam<TReducer>(&mut self, chunk: &ChunkLocation, reduce_types: &mut TReducer) -> Vec<TypeSet>
+        where TReducer: WaveFunctionReducer<TCellType, TTopology> + ?Sized
+    {
+        if let Some(seam) = self.top_seams.get(chunk) {
+            return seam.clone();
+        }
+
+        let cols = self.chunk_cols as i64;
+        let start = self.corner(chunk, reduce_types);
+        let end = self.corner(&ChunkLocation::new(chunk.row, chunk.col + 1), reduce_types);
+        let fixed_cells = vec![
+            (GridLocation::new(0, 0), start.center),
+            (GridLocation::new(0, 1), start.right),
+            (GridLocation::new(0, cols - 1), end.left),
+            (GridLocation::new(0, cols), end.center),
+        ];
+
+        let random = self.part_random(WorldPart::TopSeam, chunk);
+        let seam = self.collapse(1, self.chunk_cols + 1, fixed_cells, random, reduce_types).into_iter().map(|cell| cell.possible_types).collect::<Vec<_>>();
+        self.top_seams.insert(*chunk, seam.clone());
+        seam
+    }
+
+    /// Collapses a chunk's left column between the corners at either end
+    fn left_seam<TReducer>(&mut self, chunk: &ChunkLocation, reduce_types: &mut TReducer) -> Vec<TypeSet>
+        where TReducer: WaveFunctionReducer<TCellType, TTopology> + ?Sized
+    {
+        if let Some(seam) = self.left_seams.get(chunk) {
+            return seam.clone();
+        }
+
+        let rows = self.chunk_rows as i64;
+        let start = self.corner(chunk, reduce_types);
+        let end = self.corner(&ChunkLocation::new(chunk.row + 1, chunk.col), reduce_types);
+        let fixed_cells = vec![
+            (GridLocation::new(0, 0), start.center),
+            (GridLocation::new(1, 0), start.down),
+            (GridLocation::new(rows - 1, 0), end.up),
+            (GridLocation::new(rows, 0), end.center),
+        ];
+
+        let random = self.part_random(WorldPart::LeftSeam, chunk);
+        let seam = self.collapse(self.chunk_rows + 1, 1, fixed_cells, random, reduce_types).into_iter().map(|cell| cell.possible_types).collect::<Vec<_>>();
+        self.left_seams.insert(*chunk, seam.clone());
+        seam
+    }
+
+    /// Collapses everything inside a chunk's seams. The seams of the chunks below and to the right close it in.
+    fn collapse_interior<TReducer>(&mut self, chunk: &ChunkLocation, reduce_types: &mut TReducer) -> WorldChunk
+        where TReducer: WaveFunctionReducer<TCellType, TTopology> + ?Sized
+    {
+        let (rows, cols) = (self.chunk_rows as i64, self.chunk_cols as i64);
+        let top = self.top_seam(chunk, reduce_types);
+        let bottom = self.top_seam(&ChunkLocation::new(chunk.row + 1, chunk.col), reduce_types);
+        let left = self.left_seam(chunk, reduce_types);
+        let right = self.left_seam(&ChunkLocation::new(chunk.row, chunk.col + 1), reduce_types);
+
+        let mut fixed_cells = vec![];
+        for col in 0..=cols {
+            fixed_cells.push((GridLocation::new(0, col), top[col as usize].clone()));
+            fixed_cells.push((GridLocation::new(rows, col), bottom[col as usize].clone()));
+        }
+
+        for row in 1..rows {
+            fixed_cells.push((GridLocation::new(row, 0), left[row as usize].clone()));
+            fixed_cells.push((GridLocation::new(row, cols), right[row as usize].clone()));
+        }
+
+        let random = self.part_random(WorldPart::Interior, chunk);
+        let collapsed_cells = self.collapse(self.chunk_rows + 1, self.chunk_cols + 1, fixed_cells, random, reduce_types);
+
+        // Keep everything but the bottom row and right column, which belong to the neighboring chunks
+        WorldChunk {
+            location: *chunk,
+            rows: self.chunk_rows,
+            cols: self.chunk_cols,
+            cells: collapsed_cells.into_iter()
+                .filter(|cell| cell.location.row < rows && cell.location.col < cols)
+                .map(|cell| cell.possible_types)
+                .collect(),
+        }
+    }
+
+    /// Gets the random stream for a single part of the world. The world can't use one stream for everything, since then
+    /// a chunk's content would depend on what was generated before it.
+    fn part_random(&self, part: WorldPart, chunk: &ChunkLocation) -> SeededRandom {
+        SeededRandom::new(self.seed)
+            .substream(part as u64)
+            .substream(chunk.row as u64)
+            .substream(chunk.col as u64)
+    }
+
+    /// Runs a full collapse on a small grid with some cells fixed ahead of time, returning every cell row by row.
+    /// The grid always starts a whole number of periods away from a chunk's top left, so it lines up with the world's pattern.
+    fn collapse<TReducer>(&self, rows: usize, cols: usize, fixed_cells: Vec<(GridLocation, TypeSet)>, mut random: SeededRandom, reduce_types: &mut TReducer) -> Vec<PendingCell>
+        where TReducer: WaveFunctionReducer<TCellType, TTopology> + ?Sized
+    {
+        let mut wfc = WaveFunctionCollapseContext::with_topology(rows, cols, self.registry.clone(), self.topology.clone());
+
+        // Fixed cells are applied before backtracking is turned on, so they can never be rolled back. A seam that ran into a
+        // contradiction has nothing to offer, so its empty cells are left open rather than emptying out the whole chunk.
+        wfc.apply_type_sets(fixed_cells.into_iter().filter(|(_, types)| !types.is_empty()).collect());
+        wfc.iterate_queue_complete(reduce_types);
+        wfc.set_backtrack_limit(self.backtrack_limit);
+
+        let weights = &self.weights;
+        let registry = &self.registry;
+        loop {
+            let next_location = wfc.select_cell(
+                ObservationStrategy::MinEntropy,
+                &|cell_type| registry.index_of(cell_type).map_or(0.0, |index| weights[index]),
+                &mut |count| random.index_below(count),
+            );
+
+            match next_location {
+                None => break,
+                Some(location) => {
+                    let possible_types = &wfc.get_grid().get_cell(&location).unwrap().possible_types;
+                    let chosen_type = self.pick_type(possible_types, &mut random);
+                    let chosen_types = match chosen_type {
+                        None => self.registry.none(),
+                        Some(index) => TypeSet::single(self.registry.len(), index),
+                    };
+
+                    wfc.apply_type_sets(vec![(location, chosen_types)]);
+                    wfc.iterate_queue_complete(reduce_types);
+                },
+            }
+        }
+
+        wfc.get_grid().cells().to_vec()
+    }
+
+    /// Picks one of the types in the set, weighted by how likely each is. Types with no weight are only picked if nothing
+    /// else is possible.
+    fn pick_type(&self, possible_types: &TypeSet, random: &mut SeededRandom) -> Option<usize> {
+        let total: f64 = possible_types.iter().map(|index| self.weights[index]).sum();
+        if total <= 0.0 {
+            let count = possible_types.count();
+            return if count == 0 { None } else { possible_types.iter().nth(random.index_below(count)) };
+        }
+
+        let mut value = random.next_f64() * total;
+        for index in possible_types.iter() {
+            if value < self.weights[index] {
+                return Some(index);
+            }
+
+            value -= self.weights[index];
+        }
+
+        possible_types.iter().filter(|index| self.weights[*index] > 0.0).last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wfc::adjacency::*;
+
+    /// Three types in a line, each only touching itself and the types either side of it
+    fn gradient_rules() -> AdjacencyRules<char> {
+        let mut rules = AdjacencyRules::new(&['a', 'b', 'c']);
+        rules.allow_all_directions(&'a', &'a');
+        rules.allow_all_directions(&'a', &'b');
+        rules.allow_all_directions(&'b', &'b');
+        rules.allow_all_directions(&'b', &'c');
+        rules.allow_all_directions(&'c', &'c');
+
+        rules
+    }
+
+    fn gradient_world(rules: &AdjacencyRules<char>) -> InfiniteWorld<char> {
+        let mut world = InfiniteWorld::new(7, 4, 4, rules.get_shared_registry(), &|_| 1.0).unwrap();
+        world.set_backtrack_limit(16);
+
+        world
+    }
+
+    #[test]
+    fn a_chunk_comes_out_the_same_whatever_was_generated_before_it() {
+        let mut rules = gradient_rules();
+        let center = ChunkLocation::new(1, 1);
+        let alone = gradient_world(&rules).generate_chunk(&center, &mut rules).clone();
+
+        let mut world = gradient_world(&rules);
+        for row in (0..3).rev() {
+            for col in (0..3).rev() {
+                world.generate_chunk(&ChunkLocation::new(row, col), &mut rules);
+            }
+        }
+
+        assert_eq!(world.get_chunk(&center), Some(&alone));
+    }
+
+    #[test]
+    fn neighboring_chunks_follow_the_rules_across_their_seams() {
+        let mut rules = gradient_rules();
+        let mut world = gradient_world(&rules);
+        world.generate_area(&GridLocation::new(0, 0), &GridLocation::new(7, 7), &mut rules);
+
+        let registry = world.get_registry();
+        let type_at = |loc: &GridLocation| {
+            let types = world.get_types(loc).unwrap();
+            assert_eq!(types.count(), 1, "{:?} didn't settle", loc);
+            *registry.get(types.first().unwrap())
+        };
+
+        for row in 0..8 {
+            for col in 0..8 {
+                let loc = GridLocation::new(row, col);
+                for direction in 0..world.get_topology().direction_count() {
+                    let neighbor = world.get_topology().neighbor(&loc, direction);
+                    if world.get_types(&neighbor).is_some() {
+                        assert!(rules.is_allowed(&type_at(&loc), direction, &type_at(&neighbor)), "{:?} doesn't fit with {:?}", loc, neighbor);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unloaded_chunks_come_back_the_same() {
+        let mut rules = gradient_rules();
+        let mut world = gradient_world(&rules);
+        let (top_left, bottom_right) = (GridLocation::new(0, 0), GridLocation::new(7, 7));
+        world.generate_area(&top_left, &bottom_right, &mut rules);
+        let chunks: Vec<WorldChunk> = (0..4).map(|index| world.get_chunk(&ChunkLocation::new(index / 2, index % 2)).unwrap().clone()).collect();
+
+        world.unload_outside(&ChunkLocation::new(5, 5), &ChunkLocation::new(5, 5));
+        assert_eq!(world.get_chunk(&ChunkLocation::new(0, 0)), None);
+        world.generate_area(&top_left, &bottom_right, &mut rules);
+
+        for chunk in chunks.iter() {
+            assert_eq!(world.get_chunk(&chunk.location), Some(chunk));
+        }
+    }
+
+    #[test]
+    fn chunk_sizes_that_break_the_pattern_are_refused() {
+        let registry = gradient_rules().get_shared_registry();
+
+        assert!(InfiniteWorld::new(0, 2, 4, registry.clone(), &|_| 1.0).is_err());
+        assert!(InfiniteWorld::new(0, 5, 4, registry, &|_| 1.0).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use crate::wfc::*;
+
+/// Something that happened while a WaveFunctionCollapseContext was running
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum WfcEvent {
+    /// A cell was added to the queue
+    CellEnqueued(GridLocation),
+    /// A reducer narrowed down a cell's possible types
+    DomainReduced {
+        location: GridLocation,
+        before: TypeSet,
+        after: TypeSet,
+    },
+    /// A cell was left with no possible types
+    Contradiction(GridLocation),
+    /// Types were applied to a cell from outside the solver, like a paint or a random pick
+    DecisionMade {
+        location: GridLocation,
+        previous_types: TypeSet,
+        chosen_types: TypeSet,
+    },
+    /// A decision was rolled back, and the types it chose banned from the cell
+    Backtracked {
+        location: GridLocation,
+        banned_types: TypeSet,
+    },
+}
+
+impl WfcEvent {
+    /// The cell the event happened to
+    pub fn get_location(&self) -> &GridLocation {
+        match self {
+            WfcEvent::CellEnqueued(location) => location,
+            WfcEvent::DomainReduced { location, .. } => location,
+            WfcEvent::Contradiction(location) => location,
+            WfcEvent::DecisionMade { location, .. } => location,
+            WfcEvent::Backtracked { location, .. } => location,
+        }
+    }
+}
+
+impl fmt::Display for WfcEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let location = self.get_location();
+        match self {
+            WfcEvent::CellEnqueued(_) => write!(f, "Enqueued ({}, {})", location.row, location.col),
+            WfcEvent::DomainReduced { before, after, .. } => {
+                write!(f, "Reduced ({}, {}) from {} to {} types", location.row, location.col, before.count(), after.count())
+            },
+            WfcEvent::Contradiction(_) => write!(f, "Contradiction at ({}, {})", location.row, location.col),
+            WfcEvent::DecisionMade { previous_types, chosen_types, .. } => {
+                write!(f, "Decided ({}, {}) from {} to {} types", location.row, location.col, previous_types.count(), chosen_types.count())
+            },
+            WfcEvent::Backtracked { banned_types, .. } => {
+                write!(f, "Backtracked ({}, {}), banning {} types", location.row, location.col, banned_types.count())
+            },
+        }
+    }
+}
+
+/// Anything that wants to hear about a context's events
+pub trait WfcObserver {
+    fn on_event(&mut self, event: &WfcEvent);
+}
+
+impl<F> WfcObserver for F where F: FnMut(&WfcEvent) {
+    fn on_event(&mut self, event: &WfcEvent) {
+        self(event)
+    }
+}
+
+/// Lets an observer be subscribed to a context and still be read from outside it
+pub type SharedWfcObserver = Arc<Mutex<dyn WfcObserver + Send>>;
+
+/// The observers subscribed to a context.
+/// Observers aren't part of a context's state, so they're ignored when comparing contexts, and a cloned context keeps
+/// reporting to the same ones.
+#[derive(Clone, Default)]
+pub struct WfcObservers {
+    next_id: usize,
+    observers: Vec<(usize, SharedWfcObserver)>,
+}
+
+impl WfcObservers {
+    /// Adds an observer, returning an id that can be used to unsubscribe it
+    pub fn subscribe(&mut self, observer: SharedWfcObserver) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.observers.push((id, observer));
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: usize) {
+        self.observers.retain(|(observer_id, _)| *observer_id != id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    /// Sends an event to every observer. The event is only built if someone's listening, since most runs have no observers.
+    pub fn emit(&self, event: impl FnOnce() -> WfcEvent) {
+        if self.observers.is_empty() {
+            return;
+        }
+
+        let event = event();
+        for (_, observer) in self.observers.iter() {
+            observer.lock().unwrap().on_event(&event);
+        }
+    }
+}
+
+impl fmt::Debug for WfcObservers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WfcObservers({})", self.observers.len())
+    }
+}
+
+impl PartialEq for WfcObservers {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// An observer that keeps the most recent events, along with a count of every kind of event it's seen
+#[derive(Debug, PartialEq, Clone)]
+pub struct WfcEventLog {
+    limit: usize,
+    events: VecDeque<WfcEvent>,
+    pub enqueued_count: usize,
+    pub reduced_count: usize,
+    pub contradiction_count: usize,
+    pub decision_count: usize,
+    pub backtrack_count: usize,
+}
+
+impl WfcEventLog {
+    /// Creates a log that keeps up to `limit` events. Older events still count, they just aren't kept.
+    pub fn new(limit: usize) -> WfcEventLog {
+        WfcEventLog {
+            limit,
+            events: VecDeque::new(),
+            enqueued_count: 0,
+            reduced_count: 0,
+            contradiction_count: 0,
+            decision_count: 0,
+            backtrack_count: 0,
+        }
+    }
+
+    /// The kept events, oldest first
+    pub fn get_events(&self) -> &VecDeque<WfcEvent> {
+        &self.events
+    }
+
+    pub fn clear(&mut self) {
+        *self = WfcEventLog::new(self.limit);
+    }
+}
+
+impl WfcObserver for WfcEventLog {
+    fn on_event(&mut self, event: &WfcEvent) {
+        match event {
+            WfcEvent::CellEnqueued(_) => self.enqueued_count += 1,
+            WfcEvent::DomainReduced { .. } => self.reduced_count += 1,
+            WfcEvent::Contradiction(_) => self.contradiction_count += 1,
+            WfcEvent::DecisionMade { .. } => self.decision_count += 1,
+            WfcEvent::Backtracked { .. } => self.backtrack_count += 1,
+        }
+
+        if self.limit == 0 {
+            return;
+        }
+
+        if self.events.len() == self.limit {
+            self.events.pop_front();
+        }
+
+        self.events.push_back(event.clone());
+    }
+}
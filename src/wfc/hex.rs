@@ -0,0 +1,250 @@
+use std::ops::{Add, Sub};
+use crate::wfc::GridLocation;
+use crate::wfc::topology::HexOrientation;
+
+/// A hex position in cube coordinates, where q + r + s is always 0.
+/// Offset GridLocations are handy for storage, but distances, rotations and lines only work out in cube coordinates.
+/// The same coordinates work for both orientations; only the conversion to and from offset locations differs.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct CubeCoord {
+    pub q: i64,
+    pub r: i64,
+    pub s: i64,
+}
+
+/// Cube offsets to each neighbor, in the same order as HexTopology's directions for either orientation
+const CUBE_DIRECTIONS: [CubeCoord; 6] = [
+    CubeCoord { q: 0, r: -1, s: 1 },
+    CubeCoord { q: 1, r: -1, s: 0 },
+    CubeCoord { q: 1, r: 0, s: -1 },
+    CubeCoord { q: 0, r: 1, s: -1 },
+    CubeCoord { q: -1, r: 1, s: 0 },
+    CubeCoord { q: -1, r: 0, s: 1 },
+];
+
+impl CubeCoord {
+    /// Creates a coordinate from its axial q and r, working out s
+    pub fn new(q: i64, r: i64) -> CubeCoord {
+        CubeCoord {
+            q,
+            r,
+            s: -q - r,
+        }
+    }
+
+    /// Converts a location on the default pointy top grid
+    pub fn from_location(loc: &GridLocation) -> CubeCoord {
+        CubeCoord::from_offset(loc, HexOrientation::PointyTop)
+    }
+
+    /// Converts back to a location on the default pointy top grid
+    pub fn to_location(self) -> GridLocation {
+        self.to_offset(HexOrientation::PointyTop)
+    }
+
+    /// Converts an offset location. Pointy top grids shift odd-index rows right, and flat top grids shift odd-index columns down.
+    pub fn from_offset(loc: &GridLocation, orientation: HexOrientation) -> CubeCoord {
+        // & 1 rather than % 2 so negative rows and columns get the right parity
+        match orientation {
+            HexOrientation::PointyTop => CubeCoord::new(loc.col - (loc.row - (loc.row & 1)) / 2, loc.row),
+            HexOrientation::FlatTop => CubeCoord::new(loc.col, loc.row - (loc.col - (loc.col & 1)) / 2),
+        }
+    }
+
+    /// Converts to an offset location for the given orientation
+    pub fn to_offset(self, orientation: HexOrientation) -> GridLocation {
+        match orientation {
+            HexOrientation::PointyTop => GridLocation::new(self.r, self.q + (self.r - (self.r & 1)) / 2),
+            HexOrientation::FlatTop => GridLocation::new(self.r + (self.q - (self.q & 1)) / 2, self.q),
+        }
+    }
+
+    /// The offset to the neighbor in the given direction
+    pub fn direction(direction: usize) -> CubeCoord {
+        CUBE_DIRECTIONS[direction % 6]
+    }
+
+    /// The neighbor in the given direction
+    pub fn neighbor(&self, direction: usize) -> CubeCoord {
+        *self + CubeCoord::direction(direction)
+    }
+
+    /// Multiplies each component, e.g. to step several cells in one direction
+    pub fn scale(&self, factor: i64) -> CubeCoord {
+        CubeCoord::new(self.q * factor, self.r * factor)
+    }
+
+    /// The number of steps from here to the other coordinate
+    pub fn distance(&self, other: &CubeCoord) -> i64 {
+        let difference = *self - *other;
+        difference.q.abs().max(difference.r.abs()).max(difference.s.abs())
+    }
+
+    /// The number of steps from the origin
+    pub fn length(&self) -> i64 {
+        self.distance(&CubeCoord::new(0, 0))
+    }
+
+    /// Rotates 60 degrees clockwise around the center
+    pub fn rotate_right(&self, center: &CubeCoord) -> CubeCoord {
+        let relative = *self - *center;
+        *center + CubeCoord::new(-relative.r, -relative.s)
+    }
+
+    /// Rotates 60 degrees counterclockwise around the center
+    pub fn rotate_left(&self, center: &CubeCoord) -> CubeCoord {
+        let relative = *self - *center;
+        *center + CubeCoord::new(-relative.s, -relative.q)
+    }
+
+    /// Rotates by a number of 60 degree steps around the center. Positive is clockwise.
+    pub fn rotate(&self, center: &CubeCoord, steps: i64) -> CubeCoord {
+        let steps = steps.rem_euclid(6);
+        (0..steps).fold(*self, |coord, _| coord.rotate_right(center))
+    }
+
+    /// Every coordinate exactly `radius` steps away, going clockwise from the bottom left corner.
+    /// A radius of 0 is just the center.
+    pub fn ring(center: &CubeCoord, radius: i64) -> Vec<CubeCoord> {
+        if radius <= 0 {
+            return vec![*center];
+        }
+
+        let mut results = vec![];
+        // Starting from the bottom left corner, walking each direction in order traces the sides clockwise
+        let mut coord = *center + CubeCoord::direction(4).scale(radius);
+        for direction in 0..6 {
+            for _ in 0..radius {
+                results.push(coord);
+                coord = coord.neighbor(direction);
+            }
+        }
+
+        results
+    }
+
+    /// The center followed by each ring out to `radius`, so closer coordinates always come first
+    pub fn spiral(center: &CubeCoord, radius: i64) -> Vec<CubeCoord> {
+        (0..=radius.max(0)).flat_map(|ring_radius| CubeCoord::ring(center, ring_radius)).collect()
+    }
+
+    /// Every coordinate on a straight line between the two, including both ends
+    pub fn line_to(&self, other: &CubeCoord) -> Vec<CubeCoord> {
+        let distance = self.distance(other);
+        if distance == 0 {
+            return vec![*self];
+        }
+
+        // Nudge the start slightly so points exactly on an edge between two hexes always round the same way
+        let (start_q, start_r, start_s) = (self.q as f64 + 1e-6, self.r as f64 + 1e-6, self.s as f64 - 2e-6);
+        (0..=distance).map(|step| {
+            let t = step as f64 / distance as f64;
+            CubeCoord::round(
+                start_q + (other.q as f64 - start_q) * t,
+                start_r + (other.r as f64 - start_r) * t,
+                start_s + (other.s as f64 - start_s) * t,
+            )
+        }).collect()
+    }
+
+    /// Rounds fractional cube coordinates to the hex containing them
+    fn round(q: f64, r: f64, s: f64) -> CubeCoord {
+        let (mut rounded_q, mut rounded_r, rounded_s) = (q.round(), r.round(), s.round());
+        let (q_diff, r_diff, s_diff) = ((rounded_q - q).abs(), (rounded_r - r).abs(), (rounded_s - s).abs());
+
+        // Rounding each part on its own can break q + r + s = 0, so rebuild whichever part moved the most from the others
+        if q_diff > r_diff && q_diff > s_diff {
+            rounded_q = -rounded_r - rounded_s;
+        } else if r_diff > s_diff {
+            rounded_r = -rounded_q - rounded_s;
+        }
+
+        CubeCoord::new(rounded_q as i64, rounded_r as i64)
+    }
+}
+
+impl Add for CubeCoord {
+    type Output = CubeCoord;
+
+    fn add(self, other: CubeCoord) -> CubeCoord {
+        CubeCoord::new(self.q + other.q, self.r + other.r)
+    }
+}
+
+impl Sub for CubeCoord {
+    type Output = CubeCoord;
+
+    fn sub(self, other: CubeCoord) -> CubeCoord {
+        CubeCoord::new(self.q - other.q, self.r - other.r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wfc::topology::*;
+
+    #[test]
+    fn offset_locations_survive_a_round_trip_in_both_orientations() {
+        for orientation in [HexOrientation::PointyTop, HexOrientation::FlatTop] {
+            for row in -3..4 {
+                for col in -3..4 {
+                    let location = GridLocation::new(row, col);
+                    assert_eq!(CubeCoord::from_offset(&location, orientation).to_offset(orientation), location);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cube_directions_match_the_hex_topology_neighbors() {
+        for orientation in [HexOrientation::PointyTop, HexOrientation::FlatTop] {
+            for location in [GridLocation::new(2, 2), GridLocation::new(3, 3), GridLocation::new(-1, 0)] {
+                let coord = CubeCoord::from_offset(&location, orientation);
+                let neighbors: Vec<GridLocation> = (0..6).map(|direction| coord.neighbor(direction).to_offset(orientation)).collect();
+                assert_eq!(neighbors, HexTopology { orientation }.neighbors(&location));
+            }
+        }
+    }
+
+    #[test]
+    fn distance_counts_steps_in_any_direction() {
+        let origin = CubeCoord::new(0, 0);
+
+        assert_eq!(origin.distance(&CubeCoord::direction(2).scale(4)), 4);
+        assert_eq!(origin.distance(&(CubeCoord::direction(0).scale(2) + CubeCoord::direction(1).scale(3))), 5);
+        assert_eq!(CubeCoord::new(3, -1).distance(&CubeCoord::new(-2, 4)), CubeCoord::new(-2, 4).distance(&CubeCoord::new(3, -1)));
+    }
+
+    #[test]
+    fn six_rotations_come_back_around() {
+        let center = CubeCoord::new(1, -2);
+        let coord = CubeCoord::new(4, 0);
+
+        assert_eq!(coord.rotate(&center, 6), coord);
+        assert_eq!(coord.rotate_right(&center).rotate_left(&center), coord);
+        assert_eq!(coord.rotate(&center, -1), coord.rotate_left(&center));
+        assert_eq!(coord.rotate(&center, 2).distance(&center), coord.distance(&center));
+    }
+
+    #[test]
+    fn rings_hold_six_coordinates_per_step_of_radius() {
+        let center = CubeCoord::new(2, 1);
+        let ring = CubeCoord::ring(&center, 3);
+
+        assert_eq!(ring.len(), 18);
+        assert!(ring.iter().all(|coord| coord.distance(&center) == 3));
+        assert_eq!(CubeCoord::spiral(&center, 2).len(), 19);
+    }
+
+    #[test]
+    fn lines_step_one_cell_at_a_time() {
+        let start = CubeCoord::new(-2, 3);
+        let end = CubeCoord::new(3, -1);
+        let line = start.line_to(&end);
+
+        assert_eq!(line.len() as i64, start.distance(&end) + 1);
+        assert_eq!((line[0], line[line.len() - 1]), (start, end));
+        assert!(line.windows(2).all(|pair| pair[0].distance(&pair[1]) == 1));
+    }
+}
@@ -1,24 +1,211 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use std::cell::RefCell;
 use std::collections::VecDeque;
 
+pub mod adjacency;
+pub mod chunked;
+pub mod events;
+pub mod hex;
+pub mod overlapping;
+pub mod random;
+pub mod topology;
+
+use topology::*;
+use events::*;
+
+/// A compact set of cell types, stored as one bit per index into a TypeRegistry
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct TypeSet {
+    words: Vec<u64>,
+}
+
+impl TypeSet {
+    /// Creates a set with room for `capacity` types and nothing in it
+    pub fn empty(capacity: usize) -> TypeSet {
+        TypeSet {
+            words: vec![0; capacity.div_ceil(64)],
+        }
+    }
+
+    /// Creates a set containing every index below `capacity`
+    pub fn full(capacity: usize) -> TypeSet {
+        let mut set = TypeSet::empty(capacity);
+        for index in 0..capacity {
+            set.insert(index);
+        }
+
+        set
+    }
+
+    /// Creates a set containing only the given index
+    pub fn single(capacity: usize, index: usize) -> TypeSet {
+        let mut set = TypeSet::empty(capacity);
+        set.insert(index);
+        set
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    pub fn insert(&mut self, index: usize) {
+        self.words[index / 64] |= 1 << (index % 64);
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        self.words[index / 64] &= !(1 << (index % 64));
+    }
+
+    /// The number of types in the set
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+
+    /// The lowest index in the set
+    pub fn first(&self) -> Option<usize> {
+        self.iter().next()
+    }
+
+    /// Iterates the indices in the set, lowest first
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(word_index, word)| {
+            let mut remaining = *word;
+            std::iter::from_fn(move || {
+                if remaining == 0 {
+                    None
+                } else {
+                    let bit = remaining.trailing_zeros() as usize;
+                    remaining &= remaining - 1;
+                    Some(word_index * 64 + bit)
+                }
+            })
+        })
+    }
+
+    /// Keeps only the types also in `other`
+    pub fn intersect_with(&mut self, other: &TypeSet) {
+        for (word, other_word) in self.words.iter_mut().zip(other.words.iter()) {
+            *word &= *other_word;
+        }
+    }
+
+    /// Adds every type in `other`
+    pub fn union_with(&mut self, other: &TypeSet) {
+        for (word, other_word) in self.words.iter_mut().zip(other.words.iter()) {
+            *word |= *other_word;
+        }
+    }
+
+    /// Removes every type in `other`
+    pub fn subtract(&mut self, other: &TypeSet) {
+        for (word, other_word) in self.words.iter_mut().zip(other.words.iter()) {
+            *word &= !*other_word;
+        }
+    }
+
+    /// Whether the two sets have any type in common
+    pub fn intersects(&self, other: &TypeSet) -> bool {
+        self.words.iter().zip(other.words.iter()).any(|(word, other_word)| word & other_word != 0)
+    }
+}
+
+/// Assigns each cell type the index it uses in a TypeSet
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TypeRegistry<TCellType: Clone> {
+    types: Vec<TCellType>,
+}
+
+impl<TCellType: Clone + PartialEq> TypeRegistry<TCellType> {
+    pub fn new(types: &[TCellType]) -> TypeRegistry<TCellType> {
+        TypeRegistry {
+            types: types.to_vec(),
+        }
+    }
+
+    /// The number of registered types
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    /// Gets the type at the given index
+    pub fn get(&self, index: usize) -> &TCellType {
+        &self.types[index]
+    }
+
+    /// Gets the index of the given type, if it's registered
+    pub fn index_of(&self, cell_type: &TCellType) -> Option<usize> {
+        self.types.iter().position(|t| t == cell_type)
+    }
+
+    /// All registered types, in index order
+    pub fn types(&self) -> &Vec<TCellType> {
+        &self.types
+    }
+
+    /// A set of every registered type
+    pub fn all(&self) -> TypeSet {
+        TypeSet::full(self.types.len())
+    }
+
+    /// A set with nothing in it
+    pub fn none(&self) -> TypeSet {
+        TypeSet::empty(self.types.len())
+    }
+
+    /// Builds a set from a list of types. Unregistered types are ignored.
+    pub fn set_of(&self, types: &[TCellType]) -> TypeSet {
+        let mut set = self.none();
+        for index in types.iter().filter_map(|cell_type| self.index_of(cell_type)) {
+            set.insert(index);
+        }
+
+        set
+    }
+
+    /// Lists the types in a set
+    pub fn types_in(&self, set: &TypeSet) -> Vec<TCellType> {
+        set.iter().map(|index| self.types[index].clone()).collect()
+    }
+
+    /// Whether the set contains the given type
+    pub fn contains(&self, set: &TypeSet, cell_type: &TCellType) -> bool {
+        match self.index_of(cell_type) {
+            None => false,
+            Some(index) => set.contains(index),
+        }
+    }
+}
+
 /// A cell in the process of being generated.
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct PendingCell<TCellType: Clone> {
-    /// The remaining possible cell types this could be
-    pub possible_types: Vec<TCellType>,
+pub struct PendingCell {
+    /// The remaining possible cell types this could be, as indices into the grid's TypeRegistry
+    pub possible_types: TypeSet,
     pub location: GridLocation,
     max_cell_types: usize,
 }
 
-impl<TCellType: Clone> PendingCell<TCellType> {
+impl PendingCell {
     /// The cell has been narrowed down to 1 or 0 options
     pub fn is_settled(&self) -> bool {
-        self.possible_types.len() <= 1
+        self.possible_types.count() <= 1
     }
 
     pub fn is_untouched(&self) -> bool {
-        self.possible_types.len() == self.max_cell_types
+        self.possible_types.count() == self.max_cell_types
+    }
+
+    /// The index of the only type left, if the cell is down to exactly one
+    pub fn settled_type(&self) -> Option<usize> {
+        if self.possible_types.count() == 1 { self.possible_types.first() } else { None }
     }
 }
 
@@ -39,125 +226,441 @@ impl GridLocation {
         }
     }
 
-    /// Gets the coordinates of all Cells that touch this one. Note that these may include coordinates off the map. The order of coordinates is:
-    /// Top left
-    /// Top right
-    /// Right
-    /// Borrom right
-    /// Bottom left
-    /// Left
+    /// Gets the coordinates of all Cells that touch this one on the default pointy top hex grid. Note that these may include
+    /// coordinates off the map. See HexTopology for the order of coordinates.
     pub fn get_neighbors(&self) -> Vec<GridLocation> {
-        // The grid convention is that the first row is the upper left, the second row is shifted to the right, and so on
-        //
-        //           / \ / \ / \ / \
-        //  Row 0    |0| |1| |2| |3|
-        //           \ / \ / \ / \ / \
-        //  Row 1     | 0 | 1 | 2 | 3 |
-        //           / \ / \ / \ / \ /
-        //  Row 2    |0| |1| |2| |3|
-        //           \ / \ / \ / \ /
-
-        // So, for "Odd" (even-index) rows, left is col - 1, and "Even" (odd-index) rows, left is col.
-        let left_index = if self.row % 2 == 0 { self.col - 1 } else { self.col };
-
-        vec![
-            GridLocation::new(self.row - 1, left_index),
-            GridLocation::new(self.row - 1, left_index + 1),
-            GridLocation::new(self.row , self.col + 1),
-            GridLocation::new(self.row + 1, left_index + 1),
-            GridLocation::new(self.row + 1, left_index),
-            GridLocation::new(self.row , self.col - 1),
-        ]
-    }
-}
-
-/// A grid of hexagonal cells
+        HexTopology::default().neighbors(self)
+    }
+
+    /// Gets the index of the neighbor direction pointing back the other way (e.g. bottom right for top left) on the default hex grid
+    pub fn opposite_direction(direction: usize) -> usize {
+        HexTopology::default().opposite_direction(direction)
+    }
+}
+
+/// A grid of cells, stored row by row in one flat list. Cells are hexagonal unless another topology is given.
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct HexGrid<TCellType: Clone> {
-    pub grid: Vec<Vec<Rc<RefCell<PendingCell<TCellType>>>>>,
+pub struct HexGrid<TCellType: Clone, TTopology: Topology = HexTopology> {
+    rows: usize,
+    cols: usize,
+    cells: Vec<PendingCell>,
+    /// The types cells can be, shared by every cell's TypeSet
+    registry: Arc<TypeRegistry<TCellType>>,
+    /// How cells connect to each other
+    topology: TTopology,
+    /// Which edges join up with the opposite edge
+    wrap_mode: WrapMode,
 }
 
 /// A cell, or not ¯\_(ツ)_/¯
-pub type MaybeCell<TCellType: Clone> = Option<Rc<RefCell<PendingCell<TCellType>>>>;
+pub type MaybeCell<'a> = Option<&'a PendingCell>;
 
-impl<TCellType: Clone> HexGrid<TCellType> {
+impl<TCellType: Clone + PartialEq> HexGrid<TCellType> {
     /// Creates a new HexGrid
-    pub fn new(rows: usize, cols: usize, init_types: &Vec<TCellType>) -> HexGrid<TCellType> {
-        let mut row = 0;
-        HexGrid::<TCellType> {
-            // Not using vec! iterators because those clone references to the same cell
-            grid: (0..rows).map(|_| {
-                let mut col = 0;
-                let cell_row = (0..cols).map(|_| {
-                    let cell = Rc::new(RefCell::new(PendingCell::<TCellType> {
-                        possible_types: init_types.to_vec(),
-                        max_cell_types: init_types.len(),
-                        location: GridLocation {
-                            row,
-                            col,
-                        },
-                    }));
+    pub fn new(rows: usize, cols: usize, init_types: &[TCellType]) -> HexGrid<TCellType> {
+        HexGrid::with_registry(rows, cols, Arc::new(TypeRegistry::new(init_types)))
+    }
+
+    /// Creates a new HexGrid where every cell may be any of the registered types
+    pub fn with_registry(rows: usize, cols: usize, registry: Arc<TypeRegistry<TCellType>>) -> HexGrid<TCellType> {
+        HexGrid::with_topology(rows, cols, registry, HexTopology::default())
+    }
+}
+
+impl<TCellType: Clone + PartialEq, TTopology: Topology> HexGrid<TCellType, TTopology> {
+    /// Creates a new grid with the given topology where every cell may be any of the registered types
+    pub fn with_topology(rows: usize, cols: usize, registry: Arc<TypeRegistry<TCellType>>, topology: TTopology) -> HexGrid<TCellType, TTopology> {
+        let all_types = registry.all();
+        let cells = (0..rows * cols).map(|index| PendingCell {
+            possible_types: all_types.clone(),
+            max_cell_types: registry.len(),
+            location: GridLocation::new((index / cols) as i64, (index % cols) as i64),
+        }).collect();
 
-                    col += 1;
+        HexGrid::<TCellType, TTopology> {
+            rows,
+            cols,
+            cells,
+            registry,
+            topology,
+            wrap_mode: WrapMode::None,
+        }
+    }
 
-                    cell
-                }).collect();
+    pub fn get_row_count(&self) -> usize {
+        self.rows
+    }
 
-                row += 1;
+    pub fn get_col_count(&self) -> usize {
+        self.cols
+    }
 
-                cell_row
-            }).collect(),
+    /// Gets how cells on this grid connect
+    pub fn get_topology(&self) -> &TTopology {
+        &self.topology
+    }
+
+    /// Gets all neighbors of a location according to the grid's topology. These may include locations off the grid.
+    pub fn get_neighbors(&self, loc: &GridLocation) -> Vec<GridLocation> {
+        self.topology.neighbors(loc)
+    }
+
+    pub fn get_wrap_mode(&self) -> WrapMode {
+        self.wrap_mode
+    }
+
+    /// Makes edges join up with the opposite edge, so neighbor lookups past them land on the far side.
+    /// Errors if the grid's size would break the topology's pattern across the seam, e.g. an odd number of rows on pointy top
+    /// hexes. The wrap mode is left as it was then.
+    pub fn set_wrap_mode(&mut self, wrap_mode: WrapMode) -> Result<(), String> {
+        if wrap_mode.wraps_vertically() && !self.rows.is_multiple_of(self.topology.row_period()) {
+            return Err(format!("Wrapping vertically needs a multiple of {} rows on this topology", self.topology.row_period()));
+        }
+
+        if wrap_mode.wraps_horizontally() && !self.cols.is_multiple_of(self.topology.col_period()) {
+            return Err(format!("Wrapping horizontally needs a multiple of {} columns on this topology", self.topology.col_period()));
         }
+
+        self.wrap_mode = wrap_mode;
+
+        Ok(())
+    }
+
+    /// Gets the location a cell is stored at, wrapping around edges if needed, or None if it's off the grid
+    pub fn normalize(&self, loc: &GridLocation) -> Option<GridLocation> {
+        self.topology.bound(&self.wrap_mode.wrap(loc, self.rows, self.cols), self.rows, self.cols)
+    }
+
+    /// Gets the position of a location in the flat cell list, or None if it's off the grid
+    pub fn index_of(&self, loc: &GridLocation) -> Option<usize> {
+        self.normalize(loc).map(|loc| loc.row as usize * self.cols + loc.col as usize)
+    }
+
+    /// Gets the location of a position in the flat cell list
+    pub fn location_of(&self, index: usize) -> GridLocation {
+        GridLocation::new((index / self.cols) as i64, (index % self.cols) as i64)
     }
 
     /// Gets a reference to the cell at the given location
-    pub fn get_cell(&self, loc: &GridLocation) -> MaybeCell<TCellType> {
-        if loc.row < 0 || loc.col < 0 || loc.row >= self.grid.len() as i64 || loc.col >= self.grid[0].len() as i64 {
-            None
-        } else {
-            Some(self.grid[loc.row as usize][loc.col as usize].clone())
+    pub fn get_cell(&self, loc: &GridLocation) -> MaybeCell<'_> {
+        self.index_of(loc).map(|index| &self.cells[index])
+    }
+
+    /// Gets a mutable reference to the cell at the given location
+    pub fn get_cell_mut(&mut self, loc: &GridLocation) -> Option<&mut PendingCell> {
+        self.index_of(loc).map(move |index| &mut self.cells[index])
+    }
+
+    /// Gets the cell at a position in the flat cell list
+    pub fn get_cell_at(&self, index: usize) -> &PendingCell {
+        &self.cells[index]
+    }
+
+    /// Every cell, row by row
+    pub fn cells(&self) -> &[PendingCell] {
+        &self.cells
+    }
+
+    /// Iterates the grid a row at a time
+    pub fn rows(&self) -> impl Iterator<Item = &[PendingCell]> {
+        self.cells.chunks(self.cols.max(1))
+    }
+
+    /// Gets the registry that cells' TypeSets index into
+    pub fn get_registry(&self) -> &TypeRegistry<TCellType> {
+        &self.registry
+    }
+
+    /// Gets a shared handle to the registry, for building other grids with the same types
+    pub fn get_shared_registry(&self) -> Arc<TypeRegistry<TCellType>> {
+        self.registry.clone()
+    }
+
+    /// Whether the grid's types are indexed by the given registry: the same one, or one with the same types in the same order
+    pub fn has_registry(&self, registry: &Arc<TypeRegistry<TCellType>>) -> bool {
+        Arc::ptr_eq(&self.registry, registry) || *self.registry == **registry
+    }
+
+    /// Lists the types a cell may still be
+    pub fn cell_types(&self, cell: &PendingCell) -> Vec<TCellType> {
+        self.registry.types_in(&cell.possible_types)
+    }
+
+    /// A hash of every cell's possible types, for checking whether two grids came out the same.
+    /// It's FNV-1a over the type indices, so unlike the standard hasher it's the same on every machine and build.
+    pub fn content_hash(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+
+        let mut hash = FNV_OFFSET;
+        let mut add = |value: u64| {
+            for byte in value.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        };
+
+        add(self.rows as u64);
+        add(self.cols as u64);
+        for cell in self.cells.iter() {
+            // The count goes first so cells can't run into each other
+            add(cell.possible_types.count() as u64);
+            for index in cell.possible_types.iter() {
+                add(index as u64);
+            }
         }
+
+        hash
     }
 }
 
-/// Given a cell, its grid location, and the grid, reduce the number of possible cell types.
-/// Return true if the cell's possibility's changed, otherwise false.
-pub type WafeFunctionReducer<TCellType> = fn(loc: &GridLocation, cell: Rc<RefCell<PendingCell<TCellType>>>, grid: &HexGrid<TCellType>) -> bool;
+/// Anything that can reduce a cell's possible types, given the cell, its grid location, and the grid: a plain fn, a closure
+/// capturing its own configuration, or a rule set like AdjacencyRules. It may carry state between calls.
+pub trait WaveFunctionReducer<TCellType: Clone, TTopology: Topology = HexTopology> {
+    /// Reduce the number of possible cell types. Return the cell's new possible types if they changed, otherwise None.
+    fn reduce(&mut self, loc: &GridLocation, cell: &PendingCell, grid: &HexGrid<TCellType, TTopology>) -> Option<TypeSet>;
+}
 
-/// Holds the context of an in-progress Wave Function Collapse resolution.
+impl<TCellType: Clone, TTopology: Topology, TReducer> WaveFunctionReducer<TCellType, TTopology> for TReducer
+    where TReducer: FnMut(&GridLocation, &PendingCell, &HexGrid<TCellType, TTopology>) -> Option<TypeSet>
+{
+    fn reduce(&mut self, loc: &GridLocation, cell: &PendingCell, grid: &HexGrid<TCellType, TTopology>) -> Option<TypeSet> {
+        self(loc, cell, grid)
+    }
+}
+
+/// How the next cell to collapse is picked
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ObservationStrategy {
+    /// Any unsettled cell, picked at random
+    Random,
+    /// The unsettled cell with the lowest weighted Shannon entropy, with ties picked at random
+    MinEntropy,
+}
+
+/// Cells whose entropies are this close are considered tied
+const ENTROPY_EPSILON: f64 = 1e-9;
+
+/// Per-type weights precomputed for entropy calculations, indexed the same as the registry
+#[derive(Debug, PartialEq, Clone)]
+struct EntropyWeights {
+    weights: Vec<f64>,
+    /// w * ln(w) for each weight, so each cell only needs a single ln
+    weight_logs: Vec<f64>,
+}
+
+impl EntropyWeights {
+    fn new<TCellType: Clone + PartialEq>(registry: &TypeRegistry<TCellType>, weight: &dyn Fn(&TCellType) -> f64) -> EntropyWeights {
+        let weights: Vec<f64> = registry.types().iter().map(|cell_type| weight(cell_type).max(0.0)).collect();
+        let weight_logs = weights.iter().map(|w| if *w > 0.0 { w * w.ln() } else { 0.0 }).collect();
+
+        EntropyWeights {
+            weights,
+            weight_logs,
+        }
+    }
+
+    fn entropy(&self, types: &TypeSet) -> f64 {
+        let mut total = 0.0;
+        let mut total_logs = 0.0;
+        for index in types.iter() {
+            total += self.weights[index];
+            total_logs += self.weight_logs[index];
+        }
+
+        if total <= 0.0 {
+            return 0.0;
+        }
+
+        total.ln() - total_logs / total
+    }
+}
+
+/// Entropies from the last select_cell, kept so later calls only need to look at cells that have changed since
+#[derive(Debug, PartialEq, Clone)]
+struct EntropyCache {
+    /// The weights the entropies were worked out with. The whole cache is thrown out if these change.
+    weights: EntropyWeights,
+    /// One entry per cell, row by row. None for settled cells.
+    entropies: Vec<Option<f64>>,
+}
+
+/// A choice made from outside the solver, remembered so it can be undone if it leads to a contradiction
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct WaveFunctionCollapseContext<TCellType: Clone> {
+struct WfcDecision {
+    /// The cell the choice was made on
+    location: GridLocation,
+    /// What the cell could be before the choice
+    previous_types: TypeSet,
+    /// What the choice narrowed the cell down to
+    chosen_types: TypeSet,
+    /// The length of the trail when the choice was made. Everything after this was caused by it.
+    trail_length: usize,
+    /// The queue as it was before the choice
+    queue: VecDeque<GridLocation>,
+}
+
+/// Everything about a context that changes as it runs, saved so the context can be put back the way it was.
+/// The registry, topology and settings like the backtrack limit aren't part of it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct WfcSnapshot {
+    /// Each cell's possible types, row by row
+    cells: Vec<TypeSet>,
+    wrap_mode: WrapMode,
+    queue: VecDeque<GridLocation>,
+    decisions: VecDeque<WfcDecision>,
+    trail: Vec<(GridLocation, TypeSet)>,
+    backtrack_count: usize,
+}
+
+/// Holds the context of an in-progress Wave Function Collapse resolution.
+#[derive(Debug, PartialEq, Clone)]
+pub struct WaveFunctionCollapseContext<TCellType: Clone, TTopology: Topology = HexTopology> {
     /// The grid of cells in progress
-    grid: HexGrid<TCellType>,
+    grid: HexGrid<TCellType, TTopology>,
     /// The queue of cells to collapse
     queue: VecDeque<GridLocation>,
+    /// One flag per cell, row by row, set while the cell is in the queue
+    queued_cells: Vec<bool>,
+    /// How many decisions to remember for backtracking. 0 disables backtracking.
+    backtrack_limit: usize,
+    /// Decisions that can still be rolled back, oldest first
+    decisions: VecDeque<WfcDecision>,
+    /// Every domain change made since the oldest remembered decision, paired with the types the cell had before it
+    trail: Vec<(GridLocation, TypeSet)>,
+    /// The number of times a decision has been rolled back
+    backtrack_count: usize,
+    /// Entropies remembered between calls to select_cell
+    entropy_cache: RefCell<Option<EntropyCache>>,
+    /// One flag per cell, row by row, set when the cell's types have changed since the entropy cache was last brought up to date
+    changed_cells: RefCell<Vec<bool>>,
+    /// Everything listening for events
+    observers: WfcObservers,
+}
+
+impl<TCellType: Clone + PartialEq> WaveFunctionCollapseContext<TCellType> {
+    pub fn new(rows: usize, cols: usize, init_types: &[TCellType]) -> WaveFunctionCollapseContext<TCellType> {
+        WaveFunctionCollapseContext::with_registry(rows, cols, Arc::new(TypeRegistry::new(init_types)))
+    }
+
+    /// Creates a context whose grid uses an existing registry, so TypeSets can be shared with other grids and rules
+    pub fn with_registry(rows: usize, cols: usize, registry: Arc<TypeRegistry<TCellType>>) -> WaveFunctionCollapseContext<TCellType> {
+        WaveFunctionCollapseContext::with_topology(rows, cols, registry, HexTopology::default())
+    }
 }
 
-impl<TCellType: Clone> WaveFunctionCollapseContext<TCellType> {
-    pub fn new(rows: usize, cols: usize, init_types: &Vec<TCellType>) -> WaveFunctionCollapseContext<TCellType> {
-        WaveFunctionCollapseContext::<TCellType> {
-            grid: HexGrid::<TCellType>::new(rows, cols, init_types),
+impl<TCellType: Clone + PartialEq, TTopology: Topology> WaveFunctionCollapseContext<TCellType, TTopology> {
+    /// Creates a context on a grid with the given topology
+    pub fn with_topology(rows: usize, cols: usize, registry: Arc<TypeRegistry<TCellType>>, topology: TTopology) -> WaveFunctionCollapseContext<TCellType, TTopology> {
+        WaveFunctionCollapseContext::<TCellType, TTopology> {
+            grid: HexGrid::with_topology(rows, cols, registry, topology),
             queue: VecDeque::<GridLocation>::new(),
+            queued_cells: vec![false; rows * cols],
+            backtrack_limit: 0,
+            decisions: VecDeque::new(),
+            trail: vec![],
+            backtrack_count: 0,
+            entropy_cache: RefCell::new(None),
+            changed_cells: RefCell::new(vec![false; rows * cols]),
+            observers: WfcObservers::default(),
         }
     }
 
-    /// Sets the type of each cell at each location
+    /// Enables backtracking, remembering up to `limit` decisions. When a cell runs out of possible types, the most recent
+    /// decision is rolled back and the choice it made is banned. A limit of 0 disables backtracking.
+    pub fn set_backtrack_limit(&mut self, limit: usize) {
+        self.backtrack_limit = limit;
+        while self.decisions.len() > limit {
+            self.forget_oldest_decision();
+        }
+    }
+
+    pub fn get_backtrack_limit(&self) -> usize {
+        self.backtrack_limit
+    }
+
+    /// The number of times a decision has been rolled back
+    pub fn get_backtrack_count(&self) -> usize {
+        self.backtrack_count
+    }
+
+    /// Forget all remembered decisions, making everything so far permanent
+    pub fn clear_decisions(&mut self) {
+        self.decisions.clear();
+        self.trail.clear();
+    }
+
+    /// Sets the type of each cell at each location.
+    /// Each entry is recorded as a decision that may be rolled back if backtracking is enabled.
     pub fn apply_types(&mut self, types: Vec<(GridLocation, Vec<TCellType>)>) {
-        for cell_type in types {
-            match self.grid.get_cell(&cell_type.0) {
-                None => {},
-                Some(cell) => {
-                    cell.borrow_mut().possible_types = cell_type.1;
-                    for neighbor in cell_type.0.get_neighbors() {
-                        // Since we're directly applying something here, always re-visit
-                        self.queue_cell(&neighbor);
-                    }
-                },
+        let type_sets = types.into_iter().map(|(location, types)| (location, self.grid.get_registry().set_of(&types))).collect();
+        self.apply_type_sets(type_sets);
+    }
+
+    /// Same as apply_types, but with the types already in TypeSet form
+    pub fn apply_type_sets(&mut self, types: Vec<(GridLocation, TypeSet)>) {
+        for (location, cell_types) in types {
+            self.apply_cell_types(location, cell_types, true);
+        }
+    }
+
+    /// Sets the type of each cell at each location as a fixed part of the grid rather than a choice, so backtracking never
+    /// rolls it back. Every decision made before it becomes permanent too, since the fixed cells may only fit because of them.
+    pub fn apply_fixed_types(&mut self, types: Vec<(GridLocation, Vec<TCellType>)>) {
+        self.clear_decisions();
+
+        for (location, cell_types) in types {
+            let cell_types = self.grid.get_registry().set_of(&cell_types);
+            self.apply_cell_types(location, cell_types, false);
+        }
+    }
+
+    /// Sets a cell's types from outside the solver and queues its neighbors. Only a choice of at least one type counts as a
+    /// decision, and it's only remembered for backtracking if `is_decision` is set.
+    fn apply_cell_types(&mut self, location: GridLocation, cell_types: TypeSet, is_decision: bool) {
+        let previous_types = match self.grid.get_cell(&location) {
+            None => return,
+            Some(cell) => cell.possible_types.clone(),
+        };
+
+        if cell_types.is_empty() {
+            self.observers.emit(|| WfcEvent::Contradiction(location));
+            if self.backtrack() {
+                // Nothing left to choose from, so whatever led here was a bad call
+                return;
+            }
+        } else {
+            self.observers.emit(|| WfcEvent::DecisionMade {
+                location,
+                previous_types: previous_types.clone(),
+                chosen_types: cell_types.clone(),
+            });
+
+            if is_decision && self.backtrack_limit > 0 {
+                if self.decisions.len() == self.backtrack_limit {
+                    self.forget_oldest_decision();
+                }
+
+                self.decisions.push_back(WfcDecision {
+                    location,
+                    previous_types,
+                    chosen_types: cell_types.clone(),
+                    trail_length: self.trail.len(),
+                    queue: self.queue.clone(),
+                });
             }
         }
+
+        self.set_cell_types(&location, cell_types);
+        for neighbor in self.grid.get_neighbors(&location) {
+            // Since we're directly applying something here, always re-visit
+            self.queue_cell(&neighbor);
+        }
     }
 
-    pub fn apply_types_and_process_immediately(&mut self, types: Vec<(GridLocation, Vec<TCellType>)>, reduce_types: WafeFunctionReducer<TCellType>) {
+    pub fn apply_types_and_process_immediately<TReducer>(&mut self, types: Vec<(GridLocation, Vec<TCellType>)>, reduce_types: &mut TReducer)
+        where TReducer: WaveFunctionReducer<TCellType, TTopology> + ?Sized
+    {
         for cell_type in types {
             self.apply_types(vec![cell_type]);
             self.iterate_queue_complete(reduce_types);
@@ -166,43 +669,66 @@ impl<TCellType: Clone> WaveFunctionCollapseContext<TCellType> {
 
     /// Processes a single cell queued to be collapsed.
     /// Passing reduce_types in here to help allow this struct to live in Yew state data
-    pub fn iterate_queue(&mut self, reduce_types: WafeFunctionReducer<TCellType>) {
+    pub fn iterate_queue<TReducer>(&mut self, reduce_types: &mut TReducer)
+        where TReducer: WaveFunctionReducer<TCellType, TTopology> + ?Sized
+    {
         let coord = self.queue.pop_front();
         match coord {
-            None => return,
+            None => {},
             Some(coord) => {
-                match self.grid.get_cell(&coord) {
+                if let Some(index) = self.grid.index_of(&coord) {
+                    self.queued_cells[index] = false;
+                }
+
+                let new_types = match self.grid.get_cell(&coord) {
                     None => return,
-                    Some(cell) => {
-                        let cell_changed = reduce_types(&coord, cell.clone(), &self.grid);
-                        if cell_changed {
-                            for neighbor in coord.get_neighbors().iter() {
-                                let neighbor_cell = self.grid.get_cell(neighbor);
-                                match neighbor_cell {
-                                    None => {},
-                                    Some(neighbor_cell) => {
-                                        if !neighbor_cell.borrow().is_settled() {
-                                            self.queue_cell(neighbor);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    },
+                    Some(cell) => reduce_types.reduce(&coord, cell, &self.grid),
                 };
+
+                if let Some(new_types) = new_types {
+                    let is_contradiction = new_types.is_empty();
+                    let previous_types = self.set_cell_types(&coord, new_types);
+                    self.observers.emit(|| WfcEvent::DomainReduced {
+                        location: coord,
+                        before: previous_types.clone(),
+                        after: self.grid.get_cell(&coord).unwrap().possible_types.clone(),
+                    });
+
+                    if is_contradiction {
+                        self.observers.emit(|| WfcEvent::Contradiction(coord));
+                    }
+
+                    if !self.decisions.is_empty() {
+                        self.trail.push((coord, previous_types));
+                    }
+
+                    if is_contradiction && self.backtrack() {
+                        return;
+                    }
+
+                    for neighbor in self.grid.get_neighbors(&coord).iter() {
+                        let neighbor_cell = self.grid.get_cell(neighbor);
+                        match neighbor_cell {
+                            Some(neighbor_cell) if !neighbor_cell.is_settled() => self.queue_cell(neighbor),
+                            _ => {},
+                        }
+                    }
+                }
             },
         };
     }
 
     /// Collapse all queued cells
-    pub fn iterate_queue_complete(&mut self, reduce_types: WafeFunctionReducer<TCellType>) {
+    pub fn iterate_queue_complete<TReducer>(&mut self, reduce_types: &mut TReducer)
+        where TReducer: WaveFunctionReducer<TCellType, TTopology> + ?Sized
+    {
         while !self.queue.is_empty() {
             self.iterate_queue(reduce_types);
         }
     }
 
     /// Get an immutable reference to the grid
-    pub fn get_grid(&self) -> &HexGrid<TCellType> {
+    pub fn get_grid(&self) -> &HexGrid<TCellType, TTopology> {
         &self.grid
     }
 
@@ -212,14 +738,481 @@ impl<TCellType: Clone> WaveFunctionCollapseContext<TCellType> {
         &self.queue
     }
 
+    /// Makes the grid's edges join up with the opposite edge. See HexGrid::set_wrap_mode.
+    pub fn set_wrap_mode(&mut self, wrap_mode: WrapMode) -> Result<(), String> {
+        self.grid.set_wrap_mode(wrap_mode)
+    }
+
+    /// Whether the cell at the given location is waiting in the queue
+    pub fn is_queued(&self, loc: &GridLocation) -> bool {
+        match self.grid.index_of(loc) {
+            None => false,
+            Some(index) => self.queued_cells[index],
+        }
+    }
+
+    /// Picks the next cell to collapse, or None if every cell has settled.
+    /// `weight` gives the relative likelihood of each type, and `random_index` must return a random index below the count it is given.
+    pub fn select_cell(&self, strategy: ObservationStrategy, weight: &dyn Fn(&TCellType) -> f64, random_index: &mut dyn FnMut(usize) -> usize) -> Option<GridLocation> {
+        self.update_entropy_cache(EntropyWeights::new(self.grid.get_registry(), weight));
+
+        let location_of = |index: usize| self.grid.location_of(index);
+
+        let entropy_cache = self.entropy_cache.borrow();
+        let unsettled_cells = entropy_cache.as_ref().unwrap().entropies.iter()
+            .enumerate()
+            .filter_map(|(index, entropy)| entropy.map(|entropy| (index, entropy)));
+
+        let candidates: Vec<GridLocation> = match strategy {
+            ObservationStrategy::Random => unsettled_cells.map(|(index, _)| location_of(index)).collect(),
+            ObservationStrategy::MinEntropy => {
+                let mut min_entropy = f64::INFINITY;
+                let mut candidates = vec![];
+
+                for (index, entropy) in unsettled_cells {
+                    if entropy < min_entropy - ENTROPY_EPSILON {
+                        min_entropy = entropy;
+                        candidates.clear();
+                    }
+
+                    if entropy <= min_entropy + ENTROPY_EPSILON {
+                        candidates.push(location_of(index));
+                    }
+                }
+
+                candidates
+            },
+        };
+
+        if candidates.is_empty() {
+            None
+        } else {
+            Some(candidates[random_index(candidates.len()).min(candidates.len() - 1)])
+        }
+    }
+
+    /// The weighted Shannon entropy of a cell's remaining types. Types with no weight don't contribute.
+    pub fn cell_entropy(&self, cell: &PendingCell, weight: &dyn Fn(&TCellType) -> f64) -> f64 {
+        EntropyWeights::new(self.grid.get_registry(), weight).entropy(&cell.possible_types)
+    }
+
     /// All cells have settled.
     pub fn is_settled(&self) -> bool {
-        self.grid.grid.iter().all(|row| row.iter().all(|cell| (**cell).borrow().is_settled()))
+        self.grid.cells().iter().all(|cell| cell.is_settled())
+    }
+
+    /// Overwrites a cell's types without queuing its neighbors or recording a decision.
+    /// Returns the types the cell had before, or an empty set if the location is off the grid.
+    pub fn set_cell_types(&mut self, loc: &GridLocation, types: TypeSet) -> TypeSet {
+        match self.grid.index_of(loc) {
+            None => TypeSet::empty(0),
+            Some(index) => {
+                self.changed_cells.get_mut()[index] = true;
+                std::mem::replace(&mut self.grid.get_cell_mut(loc).unwrap().possible_types, types)
+            },
+        }
+    }
+
+    /// Starts sending events to an observer. Returns an id to unsubscribe it with.
+    pub fn subscribe(&mut self, observer: SharedWfcObserver) -> usize {
+        self.observers.subscribe(observer)
+    }
+
+    /// Stops sending events to the observer with the given id
+    pub fn unsubscribe(&mut self, id: usize) {
+        self.observers.unsubscribe(id);
+    }
+
+    /// Saves the grid's domains, the queue and the backtracking history
+    pub fn snapshot(&self) -> WfcSnapshot {
+        WfcSnapshot {
+            cells: self.grid.cells().iter().map(|cell| cell.possible_types.clone()).collect(),
+            wrap_mode: self.grid.get_wrap_mode(),
+            queue: self.queue.clone(),
+            decisions: self.decisions.clone(),
+            trail: self.trail.clone(),
+            backtrack_count: self.backtrack_count,
+        }
+    }
+
+    /// Puts the context back the way it was when the snapshot was taken. Panics if the snapshot is from a different size grid.
+    pub fn restore(&mut self, snapshot: &WfcSnapshot) {
+        if snapshot.cells.len() != self.grid.cells().len() {
+            panic!("Snapshot is from a grid with {} cells, but this one has {}", snapshot.cells.len(), self.grid.cells().len());
+        }
+
+        // Only touching the cells that differ keeps the entropy cache from being thrown out wholesale
+        for (index, types) in snapshot.cells.iter().enumerate() {
+            if self.grid.get_cell_at(index).possible_types != *types {
+                let location = self.grid.location_of(index);
+                self.set_cell_types(&location, types.clone());
+            }
+        }
+
+        // The snapshot's grid was the same size, so its wrap mode already passed set_wrap_mode's checks
+        self.grid.wrap_mode = snapshot.wrap_mode;
+        self.restore_queue(snapshot.queue.clone());
+        self.decisions = snapshot.decisions.clone();
+        self.trail = snapshot.trail.clone();
+        self.backtrack_count = snapshot.backtrack_count;
+    }
+
+    /// Brings the entropy cache up to date, only recalculating cells that changed unless the weights did too
+    fn update_entropy_cache(&self, weights: EntropyWeights) {
+        let mut entropy_cache = self.entropy_cache.borrow_mut();
+        let mut changed_cells = self.changed_cells.borrow_mut();
+        let cells = self.grid.cells().iter();
+        let entropy_of = |weights: &EntropyWeights, cell: &PendingCell| {
+            if cell.is_settled() { None } else { Some(weights.entropy(&cell.possible_types)) }
+        };
+
+        match entropy_cache.as_mut() {
+            Some(cache) if cache.weights == weights => {
+                for (index, _) in changed_cells.iter().enumerate().filter(|(_, is_changed)| **is_changed) {
+                    cache.entropies[index] = entropy_of(&cache.weights, self.grid.get_cell_at(index));
+                }
+            },
+            _ => {
+                *entropy_cache = Some(EntropyCache {
+                    entropies: cells.map(|cell| entropy_of(&weights, cell)).collect(),
+                    weights,
+                });
+            },
+        }
+
+        changed_cells.iter_mut().for_each(|is_changed| *is_changed = false);
+    }
+
+    /// Rolls back the most recent decision and bans the types it chose. If that leaves nothing, keeps rolling back.
+    /// Returns false if there was nothing left to roll back, in which case the contradiction stays on the grid.
+    fn backtrack(&mut self) -> bool {
+        while let Some(decision) = self.decisions.pop_back() {
+            while self.trail.len() > decision.trail_length {
+                let (location, types) = self.trail.pop().unwrap();
+                self.set_cell_types(&location, types);
+            }
+
+            self.restore_queue(decision.queue);
+            self.backtrack_count += 1;
+
+            let mut remaining_types = decision.previous_types.clone();
+            remaining_types.subtract(&decision.chosen_types);
+
+            if remaining_types.is_empty() {
+                self.set_cell_types(&decision.location, decision.previous_types);
+                continue;
+            }
+
+            // The ban belongs to the decision before this one, so record it in case that gets rolled back too
+            if !self.decisions.is_empty() {
+                self.trail.push((decision.location, decision.previous_types));
+            }
+
+            self.set_cell_types(&decision.location, remaining_types);
+            self.observers.emit(|| WfcEvent::Backtracked {
+                location: decision.location,
+                banned_types: decision.chosen_types.clone(),
+            });
+
+            for neighbor in self.grid.get_neighbors(&decision.location) {
+                self.queue_cell(&neighbor);
+            }
+
+            return true;
+        }
+
+        false
     }
 
+    /// Drops the oldest decision so it can no longer be rolled back
+    fn forget_oldest_decision(&mut self) {
+        self.decisions.pop_front();
+        let forget_count = match self.decisions.front() {
+            None => self.trail.len(),
+            Some(decision) => decision.trail_length,
+        };
+
+        self.trail.drain(0..forget_count);
+        for decision in self.decisions.iter_mut() {
+            decision.trail_length -= forget_count;
+        }
+    }
+
+    /// Replaces the queue with one saved earlier, bringing the queued flags back in line with it
+    fn restore_queue(&mut self, queue: VecDeque<GridLocation>) {
+        self.queued_cells.iter_mut().for_each(|is_queued| *is_queued = false);
+        for location in queue.iter() {
+            if let Some(index) = self.grid.index_of(location) {
+                self.queued_cells[index] = true;
+            }
+        }
+
+        self.queue = queue;
+    }
+
+    /// Queues the cell at the given location, unless it's already queued or off the grid
     fn queue_cell(&mut self, loc: &GridLocation) {
-        if !self.queue.contains(loc) {
-            self.queue.push_back(*loc);
+        if let Some(loc) = self.grid.normalize(loc) {
+            let index = self.grid.index_of(&loc).unwrap();
+            if !self.queued_cells[index] {
+                self.queued_cells[index] = true;
+                self.queue.push_back(loc);
+                self.observers.emit(|| WfcEvent::CellEnqueued(loc));
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+
+    /// Narrows a cell down to whatever its settled neighbors are, so two different settled neighbors leave it with nothing
+    fn match_settled_neighbors(loc: &GridLocation, cell: &PendingCell, grid: &HexGrid<usize>) -> Option<TypeSet> {
+        let mut types = cell.possible_types.clone();
+        for neighbor in grid.get_neighbors(loc) {
+            if let Some(index) = grid.get_cell(&neighbor).and_then(|neighbor_cell| neighbor_cell.settled_type()) {
+                types.intersect_with(&TypeSet::single(grid.get_registry().len(), index));
+            }
+        }
+
+        if types == cell.possible_types { None } else { Some(types) }
+    }
+
+    #[test]
+    fn backtracking_bans_the_choice_that_led_to_a_contradiction() {
+        let mut wfc = WaveFunctionCollapseContext::new(1, 3, &[0usize, 1]);
+        wfc.set_backtrack_limit(4);
+        wfc.apply_fixed_types(vec![(GridLocation::new(0, 2), vec![1])]);
+        wfc.apply_types(vec![(GridLocation::new(0, 0), vec![0])]);
+        wfc.iterate_queue_complete(&mut match_settled_neighbors);
+
+        assert_eq!(wfc.get_backtrack_count(), 1);
+        for col in 0..3 {
+            assert_eq!(wfc.get_grid().get_cell(&GridLocation::new(0, col)).unwrap().settled_type(), Some(1));
+        }
+    }
+
+    #[test]
+    fn fixed_types_make_earlier_decisions_permanent() {
+        let mut wfc = WaveFunctionCollapseContext::new(1, 3, &[0usize, 1]);
+        wfc.set_backtrack_limit(4);
+        wfc.apply_types(vec![(GridLocation::new(0, 0), vec![0])]);
+        wfc.apply_fixed_types(vec![(GridLocation::new(0, 2), vec![1])]);
+        wfc.iterate_queue_complete(&mut match_settled_neighbors);
+
+        assert_eq!(wfc.get_backtrack_count(), 0);
+        assert_eq!(wfc.get_grid().get_cell(&GridLocation::new(0, 0)).unwrap().settled_type(), Some(0));
+        assert!(wfc.get_grid().get_cell(&GridLocation::new(0, 1)).unwrap().possible_types.is_empty());
+    }
+
+    #[test]
+    fn backtracking_restores_every_cell_the_decision_changed() {
+        let mut wfc = WaveFunctionCollapseContext::new(1, 4, &[0usize, 1, 2]);
+        wfc.set_backtrack_limit(4);
+        let before = wfc.snapshot();
+        wfc.apply_types(vec![(GridLocation::new(0, 0), vec![0])]);
+        wfc.iterate_queue_complete(&mut match_settled_neighbors);
+        wfc.apply_type_sets(vec![(GridLocation::new(0, 3), wfc.get_grid().get_registry().none())]);
+
+        assert_eq!(wfc.get_backtrack_count(), 1);
+        for col in 1..4 {
+            assert_eq!(wfc.get_grid().get_cell(&GridLocation::new(0, col)).unwrap().possible_types, before.cells[col as usize]);
+        }
+
+        let mut remaining_types = TypeSet::single(3, 1);
+        remaining_types.insert(2);
+        assert_eq!(wfc.get_grid().get_cell(&GridLocation::new(0, 0)).unwrap().possible_types, remaining_types);
+    }
+
+    #[test]
+    fn an_empty_choice_is_a_contradiction_rather_than_a_decision() {
+        let mut wfc = WaveFunctionCollapseContext::new(1, 2, &[0usize, 1]);
+        let log = Arc::new(Mutex::new(WfcEventLog::new(0)));
+        wfc.subscribe(log.clone());
+        wfc.apply_type_sets(vec![(GridLocation::new(0, 0), wfc.get_grid().get_registry().none())]);
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.decision_count, 0);
+        assert_eq!(log.contradiction_count, 1);
+    }
+
+    #[test]
+    fn restoring_a_snapshot_undoes_everything_after_it() {
+        let mut wfc = WaveFunctionCollapseContext::new(3, 3, &[0usize, 1]);
+        wfc.set_backtrack_limit(4);
+        let snapshot = wfc.snapshot();
+
+        wfc.apply_types(vec![(GridLocation::new(1, 1), vec![0])]);
+        wfc.iterate_queue_complete(&mut match_settled_neighbors);
+        wfc.restore(&snapshot);
+
+        assert_eq!(wfc.snapshot(), snapshot);
+        assert!(wfc.get_grid().cells().iter().all(|cell| cell.possible_types.count() == 2));
+    }
+
+    #[test]
+    fn a_restored_context_resolves_the_same_way_again() {
+        let mut wfc = WaveFunctionCollapseContext::new(1, 4, &[0usize, 1]);
+        wfc.set_backtrack_limit(4);
+        wfc.apply_fixed_types(vec![(GridLocation::new(0, 3), vec![1])]);
+        wfc.apply_types(vec![(GridLocation::new(0, 0), vec![0])]);
+        let snapshot = wfc.snapshot();
+
+        wfc.iterate_queue_complete(&mut match_settled_neighbors);
+        let resolved = wfc.snapshot();
+        wfc.restore(&snapshot);
+        wfc.iterate_queue_complete(&mut match_settled_neighbors);
+
+        assert_eq!(wfc.snapshot(), resolved);
+        assert_eq!(wfc.get_backtrack_count(), 1);
+    }
+
+    #[test]
+    fn min_entropy_picks_the_cell_with_the_fewest_types_left() {
+        let mut wfc = WaveFunctionCollapseContext::new(1, 3, &[0usize, 1, 2]);
+        let two_types = wfc.get_grid().get_registry().set_of(&[0, 1]);
+        wfc.set_cell_types(&GridLocation::new(0, 1), two_types);
+
+        assert_eq!(wfc.select_cell(ObservationStrategy::MinEntropy, &|_| 1.0, &mut |_| 0), Some(GridLocation::new(0, 1)));
+    }
+
+    #[test]
+    fn the_entropy_cache_follows_a_restore() {
+        let mut wfc = WaveFunctionCollapseContext::new(1, 3, &[0usize, 1, 2]);
+        let two_types = wfc.get_grid().get_registry().set_of(&[0, 1]);
+        let snapshot = wfc.snapshot();
+
+        wfc.set_cell_types(&GridLocation::new(0, 0), two_types.clone());
+        assert_eq!(wfc.select_cell(ObservationStrategy::MinEntropy, &|_| 1.0, &mut |_| 0), Some(GridLocation::new(0, 0)));
+
+        wfc.restore(&snapshot);
+        wfc.set_cell_types(&GridLocation::new(0, 2), two_types);
+
+        assert_eq!(wfc.select_cell(ObservationStrategy::MinEntropy, &|_| 1.0, &mut |_| 0), Some(GridLocation::new(0, 2)));
+    }
+
+    #[test]
+    fn the_entropy_cache_follows_a_backtrack() {
+        let mut wfc = WaveFunctionCollapseContext::new(1, 3, &[0usize, 1]);
+        wfc.set_backtrack_limit(4);
+        wfc.apply_fixed_types(vec![(GridLocation::new(0, 2), vec![1])]);
+        assert!(wfc.select_cell(ObservationStrategy::MinEntropy, &|_| 1.0, &mut |_| 0).is_some());
+
+        wfc.apply_types(vec![(GridLocation::new(0, 0), vec![0])]);
+        wfc.iterate_queue_complete(&mut match_settled_neighbors);
+
+        assert_eq!(wfc.get_backtrack_count(), 1);
+        assert_eq!(wfc.select_cell(ObservationStrategy::MinEntropy, &|_| 1.0, &mut |_| 0), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Snapshot is from a grid with 4 cells")]
+    fn snapshots_only_restore_onto_the_same_size_grid() {
+        let snapshot = WaveFunctionCollapseContext::new(2, 2, &[0usize, 1]).snapshot();
+
+        WaveFunctionCollapseContext::new(3, 3, &[0usize, 1]).restore(&snapshot);
+    }
+
+    #[test]
+    fn wrapping_pointy_top_rows_needs_an_even_row_count() {
+        let mut grid = HexGrid::new(3, 3, &[0usize]);
+
+        assert!(grid.set_wrap_mode(WrapMode::Vertical).is_err());
+        assert_eq!(grid.get_wrap_mode(), WrapMode::None);
+        assert!(grid.set_wrap_mode(WrapMode::Horizontal).is_ok());
+    }
+
+    #[test]
+    fn wrapped_locations_land_on_the_far_side() {
+        let mut grid = HexGrid::new(4, 4, &[0usize]);
+        grid.set_wrap_mode(WrapMode::Both).unwrap();
+
+        assert_eq!(grid.normalize(&GridLocation::new(-1, 2)), Some(GridLocation::new(3, 2)));
+        assert_eq!(grid.normalize(&GridLocation::new(4, -1)), Some(GridLocation::new(0, 3)));
+    }
+
+    #[test]
+    fn wrapped_pointy_top_neighbors_lead_back_across_the_seam() {
+        let mut grid = HexGrid::new(4, 4, &[0usize]);
+        grid.set_wrap_mode(WrapMode::Both).unwrap();
+        let topology = *grid.get_topology();
+
+        for index in 0..grid.cells().len() {
+            let location = grid.location_of(index);
+            for (direction, neighbor) in grid.get_neighbors(&location).iter().enumerate() {
+                let neighbor = grid.normalize(neighbor).unwrap();
+                let back = topology.neighbor(&neighbor, topology.opposite_direction(direction));
+
+                assert_eq!(grid.normalize(&back), Some(location), "{:?} to {:?} doesn't lead back", location, neighbor);
+            }
+        }
+    }
+
+    #[test]
+    fn type_sets_keep_indices_across_word_boundaries() {
+        let mut set = TypeSet::empty(130);
+        for index in [0, 63, 64, 129] {
+            set.insert(index);
+        }
+
+        assert_eq!(set.count(), 4);
+        assert_eq!(set.iter().collect::<Vec<usize>>(), vec![0, 63, 64, 129]);
+        assert!(set.contains(64) && !set.contains(65));
+    }
+
+    #[test]
+    fn full_type_sets_stop_at_their_capacity() {
+        let set = TypeSet::full(65);
+
+        assert_eq!(set.count(), 65);
+        assert_eq!(set.iter().last(), Some(64));
+    }
+
+    #[test]
+    fn removing_a_type_leaves_the_rest() {
+        let mut set = TypeSet::full(70);
+        set.remove(64);
+
+        assert_eq!(set.count(), 69);
+        assert!(!set.contains(64) && set.contains(63) && set.contains(65));
+    }
+
+    #[test]
+    fn type_set_operations_combine_every_word() {
+        let mut low_and_high = TypeSet::single(100, 1);
+        low_and_high.insert(99);
+        let high = TypeSet::single(100, 99);
+
+        let mut intersection = low_and_high.clone();
+        intersection.intersect_with(&high);
+        assert_eq!(intersection, high);
+
+        let mut difference = low_and_high.clone();
+        difference.subtract(&high);
+        assert_eq!(difference, TypeSet::single(100, 1));
+
+        let mut union = difference.clone();
+        union.union_with(&high);
+        assert_eq!(union, low_and_high);
+        assert!(!difference.intersects(&high));
+    }
+
+    #[test]
+    fn empty_type_sets_have_no_first_type() {
+        let set = TypeSet::empty(10);
+
+        assert!(set.is_empty());
+        assert_eq!(set.first(), None);
+    }
+
+    #[test]
+    fn registries_build_sets_from_registered_types_only() {
+        let registry = TypeRegistry::new(&['a', 'b', 'c']);
+        let set = registry.set_of(&['c', 'z', 'a']);
+
+        assert_eq!(registry.types_in(&set), vec!['a', 'c']);
+        assert!(registry.contains(&set, &'c') && !registry.contains(&set, &'b'));
+    }
+}
@@ -0,0 +1,267 @@
+use std::sync::Arc;
+use crate::wfc::*;
+use crate::wfc::adjacency::*;
+use crate::wfc::hex::*;
+use crate::wfc::topology::*;
+
+/// A hex neighborhood seen in an example grid
+#[derive(Debug, PartialEq, Clone)]
+pub struct HexPattern<TCellType> {
+    /// The type of every cell within the model's radius, in CubeCoord::spiral order, so the center comes first
+    pub cells: Vec<TCellType>,
+    /// How many times the pattern showed up in the example
+    pub frequency: usize,
+}
+
+/// The overlapping model: rather than hand-written rules, the patterns in a painted example are the rules.
+/// Every neighborhood of the example is pulled out as a pattern, then a grid of pattern indices is collapsed where
+/// neighboring patterns have to agree wherever they overlap. Each output cell ends up the center of its pattern.
+#[derive(Debug, PartialEq, Clone)]
+pub struct OverlappingModel<TCellType: Clone> {
+    radius: i64,
+    orientation: HexOrientation,
+    patterns: Vec<HexPattern<TCellType>>,
+    /// Grids collapsed with the model hold pattern indices, so this registry is just 0 through the pattern count
+    registry: Arc<TypeRegistry<usize>>,
+    /// Which patterns may sit next to each other, found by comparing their overlapping cells
+    rules: AdjacencyRules<usize>,
+}
+
+impl<TCellType: Clone + PartialEq> OverlappingModel<TCellType> {
+    /// Learns every pattern of the given radius from an example. Only neighborhoods that are fully on the example and
+    /// fully settled count. With rotations, each neighborhood is also counted turned each of the 6 ways.
+    /// Errors if the example has no such neighborhoods.
+    pub fn from_example(example: &HexGrid<TCellType>, radius: usize, include_rotations: bool) -> Result<OverlappingModel<TCellType>, String> {
+        let radius = radius as i64;
+        let orientation = example.get_topology().orientation;
+        let origin = CubeCoord::new(0, 0);
+        let offsets = CubeCoord::spiral(&origin, radius);
+        let example_registry = example.get_registry();
+
+        let mut patterns: Vec<HexPattern<TCellType>> = vec![];
+        for index in 0..example.cells().len() {
+            let center = CubeCoord::from_offset(&example.location_of(index), orientation);
+
+            for rotation in 0..if include_rotations { 6 } else { 1 } {
+                let cells: Option<Vec<TCellType>> = offsets.iter().map(|offset| {
+                    let location = (center + offset.rotate(&origin, rotation)).to_offset(orientation);
+                    example.get_cell(&location)
+                        .and_then(|cell| cell.settled_type())
+                        .map(|type_index| example_registry.get(type_index).clone())
+                }).collect();
+
+                if let Some(cells) = cells {
+                    match patterns.iter_mut().find(|pattern| pattern.cells == cells) {
+                        Some(pattern) => pattern.frequency += 1,
+                        None => patterns.push(HexPattern { cells, frequency: 1 }),
+                    }
+                }
+            }
+        }
+
+        if patterns.is_empty() {
+            return Err(format!("The example has no fully settled neighborhoods of radius {}", radius));
+        }
+
+        let pattern_indices: Vec<usize> = (0..patterns.len()).collect();
+        let registry = Arc::new(TypeRegistry::new(&pattern_indices));
+        let mut rules = AdjacencyRules::with_registry(registry.clone(), HexTopology { orientation });
+
+        for direction in 0..6 {
+            // For a neighbor in this direction, each of our cells at p is the neighbor's cell at p - direction, as long as
+            // that's still within the neighbor's radius
+            let step = CubeCoord::direction(direction);
+            let overlap: Vec<(usize, usize)> = offsets.iter().enumerate().filter_map(|(index, offset)| {
+                let neighbor_offset = *offset - step;
+                offsets.iter().position(|other| *other == neighbor_offset).map(|neighbor_index| (index, neighbor_index))
+            }).collect();
+
+            for (pattern_index, pattern) in patterns.iter().enumerate() {
+                for (neighbor_index, neighbor) in patterns.iter().enumerate() {
+                    if overlap.iter().all(|(index, other_index)| pattern.cells[*index] == neighbor.cells[*other_index]) {
+                        rules.allow_index(pattern_index, direction, neighbor_index);
+                    }
+                }
+            }
+        }
+
+        Ok(OverlappingModel {
+            radius,
+            orientation,
+            patterns,
+            registry,
+            rules,
+        })
+    }
+
+    pub fn get_radius(&self) -> usize {
+        self.radius as usize
+    }
+
+    pub fn get_patterns(&self) -> &Vec<HexPattern<TCellType>> {
+        &self.patterns
+    }
+
+    pub fn get_rules(&self) -> &AdjacencyRules<usize> {
+        &self.rules
+    }
+
+    /// The registry for grids of pattern indices
+    pub fn get_registry(&self) -> Arc<TypeRegistry<usize>> {
+        self.registry.clone()
+    }
+
+    /// Creates a context to collapse with the model, where every cell may still be any pattern
+    pub fn new_context(&self, rows: usize, cols: usize) -> WaveFunctionCollapseContext<usize> {
+        WaveFunctionCollapseContext::with_topology(rows, cols, self.registry.clone(), HexTopology { orientation: self.orientation })
+    }
+
+    /// How likely a pattern is, going by how often it showed up in the example
+    pub fn pattern_weight(&self, pattern_index: &usize) -> f64 {
+        self.patterns[*pattern_index].frequency as f64
+    }
+
+    /// Gets the type a cell of pattern indices stands for. That's known once every pattern it could still be agrees on
+    /// the center, which may be well before it's settled on one pattern.
+    pub fn cell_type(&self, cell: &PendingCell) -> Option<&TCellType> {
+        let mut centers = cell.possible_types.iter().map(|pattern_index| &self.patterns[pattern_index].cells[0]);
+        let center = centers.next()?;
+
+        if centers.all(|other| other == center) { Some(center) } else { None }
+    }
+
+    /// Collapses the lowest entropy cell to a pattern picked by frequency, then propagates.
+    /// `random` should give values in [0, 1). Returns false once there's nothing left to collapse.
+    pub fn observe(&self, wfc: &mut WaveFunctionCollapseContext<usize>, random: &mut dyn FnMut() -> f64) -> bool {
+        let next_location = wfc.select_cell(
+            ObservationStrategy::MinEntropy,
+            &|pattern_index| self.pattern_weight(pattern_index),
+            &mut |count| (random() * count as f64) as usize,
+        );
+
+        let location = match next_location {
+            None => return false,
+            Some(location) => location,
+        };
+
+        let possible_types = wfc.get_grid().get_cell(&location).unwrap().possible_types.clone();
+        let total: f64 = possible_types.iter().map(|pattern_index| self.pattern_weight(&pattern_index)).sum();
+        let mut value = random() * total;
+        let mut chosen_pattern = possible_types.first().unwrap();
+        for pattern_index in possible_types.iter() {
+            chosen_pattern = pattern_index;
+            if value < self.pattern_weight(&pattern_index) {
+                break;
+            }
+
+            value -= self.pattern_weight(&pattern_index);
+        }
+
+        wfc.apply_type_sets(vec![(location, TypeSet::single(self.registry.len(), chosen_pattern))]);
+        wfc.iterate_queue_complete(&mut |loc: &GridLocation, cell: &PendingCell, grid: &HexGrid<usize>| self.rules.reduce(loc, cell, grid));
+
+        true
+    }
+}
+
+impl<TCellType: Clone + PartialEq> WaveFunctionReducer<usize> for OverlappingModel<TCellType> {
+    fn reduce(&mut self, loc: &GridLocation, cell: &PendingCell, grid: &HexGrid<usize>) -> Option<TypeSet> {
+        self.rules.reduce(loc, cell, grid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wfc::random::*;
+
+    /// An example where every cell is the type its row says, row by row
+    fn example_of_rows(rows: &[&str]) -> HexGrid<char> {
+        let mut example = HexGrid::new(rows.len(), rows[0].len(), &['a', 'b', 'c']);
+        for (row, types) in rows.iter().enumerate() {
+            for (col, cell_type) in types.chars().enumerate() {
+                let types = example.get_registry().set_of(&[cell_type]);
+                example.get_cell_mut(&GridLocation::new(row as i64, col as i64)).unwrap().possible_types = types;
+            }
+        }
+
+        example
+    }
+
+    #[test]
+    fn each_distinct_neighborhood_is_a_pattern_counted_every_time_it_shows_up() {
+        let example = example_of_rows(&["aaaaaa", "abaaaa", "aaaaaa"]);
+        let model = OverlappingModel::from_example(&example, 1, false).unwrap();
+
+        assert_eq!(model.get_patterns(), &vec![
+            HexPattern { cells: vec!['b', 'a', 'a', 'a', 'a', 'a', 'a'], frequency: 1 },
+            HexPattern { cells: vec!['a', 'a', 'b', 'a', 'a', 'a', 'a'], frequency: 1 },
+            HexPattern { cells: vec!['a'; 7], frequency: 2 },
+        ]);
+    }
+
+    #[test]
+    fn rotations_count_every_turn_of_a_neighborhood() {
+        let example = example_of_rows(&["aaaa", "abaa", "aaaa"]);
+        let model = OverlappingModel::from_example(&example, 1, true).unwrap();
+
+        // The b surrounded by a looks the same every way it's turned, while the a next to it doesn't
+        assert_eq!(model.get_patterns().len(), 7);
+        assert_eq!(model.get_patterns()[0].frequency, 6);
+        assert!(model.get_patterns()[1..].iter().all(|pattern| pattern.frequency == 1));
+    }
+
+    #[test]
+    fn an_example_without_a_whole_neighborhood_is_refused() {
+        let example = example_of_rows(&["ab", "ba"]);
+
+        assert!(OverlappingModel::from_example(&example, 1, false).is_err());
+    }
+
+    #[test]
+    fn patterns_may_only_touch_where_their_overlap_agrees() {
+        let example = example_of_rows(&["aaaa", "abaa", "aaaa"]);
+        let model = OverlappingModel::from_example(&example, 1, false).unwrap();
+        let rules = model.get_rules();
+
+        // Right of the b is an a whose left neighbor is that b, but never another b or an a with nothing but a around it
+        assert!(rules.is_allowed(&0, 2, &1));
+        assert!(!rules.is_allowed(&0, 2, &0));
+        assert!(!rules.is_allowed(&1, 2, &1));
+    }
+
+    #[test]
+    fn generated_grids_only_pair_neighbors_the_example_does() {
+        // Stripes two rows thick, so along a row the type never changes
+        let example = example_of_rows(&["aaaaaa", "aaaaaa", "bbbbbb", "bbbbbb", "aaaaaa", "aaaaaa", "bbbbbb", "bbbbbb"]);
+        let model = OverlappingModel::from_example(&example, 1, false).unwrap();
+        let type_at = |grid: &HexGrid<char>, loc: &GridLocation| grid.get_cell(loc).and_then(|cell| cell.settled_type()).map(|index| *grid.get_registry().get(index));
+
+        let mut example_pairs = vec![];
+        for index in 0..example.cells().len() {
+            let location = example.location_of(index);
+            for (direction, neighbor) in example.get_neighbors(&location).iter().enumerate() {
+                if let Some(neighbor_type) = type_at(&example, neighbor) {
+                    example_pairs.push((type_at(&example, &location).unwrap(), direction, neighbor_type));
+                }
+            }
+        }
+
+        let mut wfc = model.new_context(8, 8);
+        wfc.set_backtrack_limit(16);
+        let mut random = SeededRandom::new(3);
+        while model.observe(&mut wfc, &mut || random.next_f64()) {}
+
+        let grid = wfc.get_grid();
+        for index in 0..grid.cells().len() {
+            let location = grid.location_of(index);
+            let cell_type = *model.cell_type(grid.get_cell(&location).unwrap()).unwrap();
+            for (direction, neighbor) in grid.get_neighbors(&location).iter().enumerate() {
+                if let Some(neighbor_cell) = grid.get_cell(neighbor) {
+                    let pair = (cell_type, direction, *model.cell_type(neighbor_cell).unwrap());
+                    assert!(example_pairs.contains(&pair), "{:?} never happens in the example", pair);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+/// A small seeded random number generator that belongs to whatever's using it.
+/// It's SplitMix64, which only needs wrapping 64 bit integer math, so a seed gives the same numbers in wasm and native
+/// builds. Sub-streams split one seed into independent sequences, so drawing more numbers for one part of generation
+/// doesn't shift the numbers every other part gets.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SeededRandom {
+    state: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        SeededRandom {
+            state: seed,
+        }
+    }
+
+    /// Creates an independent generator for the given stream id. The same state and id always give the same stream.
+    pub fn substream(&self, stream: u64) -> SeededRandom {
+        let mut random = SeededRandom::new(self.state ^ stream);
+        random.state = random.next_u64();
+        random
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut value = self.state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94D049BB133111EB);
+        value ^ (value >> 31)
+    }
+
+    /// A value in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A value in [min, max)
+    pub fn range_f64(&mut self, min: f64, max: f64) -> f64 {
+        min + (max - min) * self.next_f64()
+    }
+
+    /// An index below count
+    pub fn index_below(&mut self, count: usize) -> usize {
+        (self.next_f64() * count as f64) as usize
+    }
+
+    /// True with the given likelihood, from 0 to 1
+    pub fn chance(&mut self, likelihood: f64) -> bool {
+        self.next_f64() < likelihood
+    }
+}
@@ -0,0 +1,366 @@
+use std::fmt::Debug;
+use crate::wfc::GridLocation;
+
+/// Describes how cells on a grid connect: which locations neighbor each other, which direction points back the other way,
+/// and which locations are on the grid at all.
+/// Directions are indices into the list returned by neighbors, so a topology must always list neighbors in the same order.
+pub trait Topology: Debug + Clone + PartialEq + Eq {
+    /// The number of neighbors each cell has
+    fn direction_count(&self) -> usize;
+
+    /// Gets the location of the neighbor in the given direction. This may be off the grid.
+    fn neighbor(&self, loc: &GridLocation, direction: usize) -> GridLocation;
+
+    /// Gets the index of the direction pointing back the other way
+    fn opposite_direction(&self, direction: usize) -> usize;
+
+    /// Gets all neighbors of a location, in direction order. Note that these may include locations off the grid.
+    fn neighbors(&self, loc: &GridLocation) -> Vec<GridLocation> {
+        (0..self.direction_count()).map(|direction| self.neighbor(loc, direction)).collect()
+    }
+
+    /// Gets the location a grid of the given size actually stores `loc` at, or None if it's off the grid
+    fn bound(&self, loc: &GridLocation, rows: usize, cols: usize) -> Option<GridLocation> {
+        if loc.row < 0 || loc.col < 0 || loc.row >= rows as i64 || loc.col >= cols as i64 {
+            None
+        } else {
+            Some(*loc)
+        }
+    }
+
+    /// Whether a row is drawn shifted half a cell to the right. Only row-offset layouts shift rows.
+    fn is_row_shifted(&self, _row: i64) -> bool {
+        false
+    }
+
+    /// The number of rows a vertically wrapping grid's height must be a multiple of, so cells keep the same shape and
+    /// neighbors across the seam
+    fn row_period(&self) -> usize {
+        1
+    }
+
+    /// The number of columns a horizontally wrapping grid's width must be a multiple of
+    fn col_period(&self) -> usize {
+        1
+    }
+}
+
+/// Which edges of a grid join up with the opposite edge
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum WrapMode {
+    /// Edges are edges
+    None,
+    /// The left and right edges join
+    Horizontal,
+    /// The top and bottom edges join
+    Vertical,
+    /// Both pairs of edges join, making a torus
+    Both,
+}
+
+impl WrapMode {
+    pub fn wraps_horizontally(&self) -> bool {
+        *self == WrapMode::Horizontal || *self == WrapMode::Both
+    }
+
+    pub fn wraps_vertically(&self) -> bool {
+        *self == WrapMode::Vertical || *self == WrapMode::Both
+    }
+
+    /// Moves a location that went past a wrapping edge back onto the grid. Locations past edges that don't wrap are left alone.
+    pub fn wrap(&self, loc: &GridLocation, rows: usize, cols: usize) -> GridLocation {
+        GridLocation::new(
+            if self.wraps_vertically() && rows > 0 { loc.row.rem_euclid(rows as i64) } else { loc.row },
+            if self.wraps_horizontally() && cols > 0 { loc.col.rem_euclid(cols as i64) } else { loc.col },
+        )
+    }
+}
+
+/// Which way the points of a hex face
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HexOrientation {
+    /// Points up and down. Rows are stacked, with every odd-index row shifted half a cell right.
+    PointyTop,
+    /// Points left and right. Columns are side by side, with every odd-index column shifted half a cell down.
+    FlatTop,
+}
+
+/// Six-sided cells in an offset layout
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct HexTopology {
+    pub orientation: HexOrientation,
+}
+
+impl HexTopology {
+    pub const POINTY_TOP: HexTopology = HexTopology { orientation: HexOrientation::PointyTop };
+    pub const FLAT_TOP: HexTopology = HexTopology { orientation: HexOrientation::FlatTop };
+}
+
+impl Default for HexTopology {
+    fn default() -> Self {
+        HexTopology::POINTY_TOP
+    }
+}
+
+impl Topology for HexTopology {
+    fn direction_count(&self) -> usize {
+        6
+    }
+
+    /// Pointy top neighbors are, in order:
+    /// Top left
+    /// Top right
+    /// Right
+    /// Bottom right
+    /// Bottom left
+    /// Left
+    ///
+    /// Flat top neighbors are, in order:
+    /// Top
+    /// Top right
+    /// Bottom right
+    /// Bottom
+    /// Bottom left
+    /// Top left
+    fn neighbor(&self, loc: &GridLocation, direction: usize) -> GridLocation {
+        match self.orientation {
+            HexOrientation::PointyTop => {
+                // The grid convention is that the first row is the upper left, the second row is shifted to the right, and so on
+                //
+                //           / \ / \ / \ / \
+                //  Row 0    |0| |1| |2| |3|
+                //           \ / \ / \ / \ / \
+                //  Row 1     | 0 | 1 | 2 | 3 |
+                //           / \ / \ / \ / \ /
+                //  Row 2    |0| |1| |2| |3|
+                //           \ / \ / \ / \ /
+
+                // So, for "Odd" (even-index) rows, left is col - 1, and "Even" (odd-index) rows, left is col.
+                let left_index = if loc.row % 2 == 0 { loc.col - 1 } else { loc.col };
+
+                match direction {
+                    0 => GridLocation::new(loc.row - 1, left_index),
+                    1 => GridLocation::new(loc.row - 1, left_index + 1),
+                    2 => GridLocation::new(loc.row, loc.col + 1),
+                    3 => GridLocation::new(loc.row + 1, left_index + 1),
+                    4 => GridLocation::new(loc.row + 1, left_index),
+                    5 => GridLocation::new(loc.row, loc.col - 1),
+                    _ => panic!("Hex direction out of range"),
+                }
+            },
+            HexOrientation::FlatTop => {
+                // Same idea turned on its side: odd-index columns sit half a cell lower, so their upper neighbors share their row
+                let upper_index = if loc.col % 2 == 0 { loc.row - 1 } else { loc.row };
+
+                match direction {
+                    0 => GridLocation::new(loc.row - 1, loc.col),
+                    1 => GridLocation::new(upper_index, loc.col + 1),
+                    2 => GridLocation::new(upper_index + 1, loc.col + 1),
+                    3 => GridLocation::new(loc.row + 1, loc.col),
+                    4 => GridLocation::new(upper_index + 1, loc.col - 1),
+                    5 => GridLocation::new(upper_index, loc.col - 1),
+                    _ => panic!("Hex direction out of range"),
+                }
+            },
+        }
+    }
+
+    fn opposite_direction(&self, direction: usize) -> usize {
+        (direction + 3) % 6
+    }
+
+    fn is_row_shifted(&self, row: i64) -> bool {
+        self.orientation == HexOrientation::PointyTop && row % 2 != 0
+    }
+
+    /// Rows (or columns when flat) alternate between shifted and not, so the seam has to land between two different ones
+    fn row_period(&self) -> usize {
+        if self.orientation == HexOrientation::PointyTop { 2 } else { 1 }
+    }
+
+    fn col_period(&self) -> usize {
+        if self.orientation == HexOrientation::FlatTop { 2 } else { 1 }
+    }
+}
+
+/// Square cells, touching either on their 4 sides or on their sides and corners
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct SquareTopology {
+    /// Whether cells touching only at a corner are neighbors
+    pub include_diagonals: bool,
+}
+
+impl Topology for SquareTopology {
+    fn direction_count(&self) -> usize {
+        if self.include_diagonals { 8 } else { 4 }
+    }
+
+    /// Neighbors go clockwise from the top. Without diagonals that's top, right, bottom, left.
+    fn neighbor(&self, loc: &GridLocation, direction: usize) -> GridLocation {
+        // Without diagonals, only every other compass direction is used
+        let compass_direction = if self.include_diagonals { direction } else { direction * 2 };
+        let (row_offset, col_offset) = match compass_direction {
+            0 => (-1, 0),
+            1 => (-1, 1),
+            2 => (0, 1),
+            3 => (1, 1),
+            4 => (1, 0),
+            5 => (1, -1),
+            6 => (0, -1),
+            7 => (-1, -1),
+            _ => panic!("Square direction out of range"),
+        };
+
+        GridLocation::new(loc.row + row_offset, loc.col + col_offset)
+    }
+
+    fn opposite_direction(&self, direction: usize) -> usize {
+        (direction + self.direction_count() / 2) % self.direction_count()
+    }
+}
+
+/// Triangular cells alternating point up and point down along each row, starting with point up at the top left
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct TriangleTopology;
+
+impl TriangleTopology {
+    /// Whether the triangle at the given location points up
+    pub fn points_up(loc: &GridLocation) -> bool {
+        (loc.row + loc.col) % 2 == 0
+    }
+}
+
+impl Topology for TriangleTopology {
+    fn direction_count(&self) -> usize {
+        3
+    }
+
+    /// Neighbors are, in order:
+    /// Left
+    /// Right
+    /// The flat side: below for triangles pointing up, above for triangles pointing down
+    fn neighbor(&self, loc: &GridLocation, direction: usize) -> GridLocation {
+        match direction {
+            0 => GridLocation::new(loc.row, loc.col - 1),
+            1 => GridLocation::new(loc.row, loc.col + 1),
+            2 => GridLocation::new(if TriangleTopology::points_up(loc) { loc.row + 1 } else { loc.row - 1 }, loc.col),
+            _ => panic!("Triangle direction out of range"),
+        }
+    }
+
+    fn opposite_direction(&self, direction: usize) -> usize {
+        match direction {
+            0 => 1,
+            1 => 0,
+            _ => 2,
+        }
+    }
+
+    /// Triangles alternate up and down both ways, so either seam needs an even count to keep them alternating
+    fn row_period(&self) -> usize {
+        2
+    }
+
+    fn col_period(&self) -> usize {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_neighbors_lead_back<TTopology: Topology>(topology: &TTopology) {
+        for row in 0..4 {
+            for col in 0..4 {
+                let loc = GridLocation::new(row, col);
+                for direction in 0..topology.direction_count() {
+                    let neighbor = topology.neighbor(&loc, direction);
+                    assert_eq!(topology.neighbor(&neighbor, topology.opposite_direction(direction)), loc, "{:?} toward {}", loc, direction);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn square_4_neighbors_go_clockwise_from_the_top() {
+        let topology = SquareTopology { include_diagonals: false };
+
+        assert_eq!(topology.neighbors(&GridLocation::new(2, 2)), vec![
+            GridLocation::new(1, 2),
+            GridLocation::new(2, 3),
+            GridLocation::new(3, 2),
+            GridLocation::new(2, 1),
+        ]);
+    }
+
+    #[test]
+    fn square_8_neighbors_include_the_corners() {
+        let topology = SquareTopology { include_diagonals: true };
+
+        assert_eq!(topology.neighbors(&GridLocation::new(2, 2)), vec![
+            GridLocation::new(1, 2),
+            GridLocation::new(1, 3),
+            GridLocation::new(2, 3),
+            GridLocation::new(3, 3),
+            GridLocation::new(3, 2),
+            GridLocation::new(3, 1),
+            GridLocation::new(2, 1),
+            GridLocation::new(1, 1),
+        ]);
+    }
+
+    #[test]
+    fn triangle_flat_sides_face_the_other_way_on_alternate_cells() {
+        let topology = TriangleTopology;
+
+        assert_eq!(topology.neighbors(&GridLocation::new(2, 2)), vec![
+            GridLocation::new(2, 1),
+            GridLocation::new(2, 3),
+            GridLocation::new(3, 2),
+        ]);
+        assert_eq!(topology.neighbors(&GridLocation::new(2, 3)), vec![
+            GridLocation::new(2, 2),
+            GridLocation::new(2, 4),
+            GridLocation::new(1, 3),
+        ]);
+    }
+
+    #[test]
+    fn flat_top_hex_neighbors_follow_the_column_shift() {
+        let topology = HexTopology::FLAT_TOP;
+
+        assert_eq!(topology.neighbors(&GridLocation::new(2, 2)), vec![
+            GridLocation::new(1, 2),
+            GridLocation::new(1, 3),
+            GridLocation::new(2, 3),
+            GridLocation::new(3, 2),
+            GridLocation::new(2, 1),
+            GridLocation::new(1, 1),
+        ]);
+        assert_eq!(topology.neighbors(&GridLocation::new(2, 3)), vec![
+            GridLocation::new(1, 3),
+            GridLocation::new(2, 4),
+            GridLocation::new(3, 4),
+            GridLocation::new(3, 3),
+            GridLocation::new(3, 2),
+            GridLocation::new(2, 2),
+        ]);
+    }
+
+    #[test]
+    fn flat_top_hexes_shift_columns_not_rows() {
+        assert!((0..4).all(|row| !HexTopology::FLAT_TOP.is_row_shifted(row)));
+        assert!(HexTopology::POINTY_TOP.is_row_shifted(1));
+        assert!(!HexTopology::POINTY_TOP.is_row_shifted(2));
+    }
+
+    #[test]
+    fn every_topology_leads_back_the_opposite_way() {
+        assert_neighbors_lead_back(&HexTopology::POINTY_TOP);
+        assert_neighbors_lead_back(&HexTopology::FLAT_TOP);
+        assert_neighbors_lead_back(&SquareTopology { include_diagonals: false });
+        assert_neighbors_lead_back(&SquareTopology { include_diagonals: true });
+        assert_neighbors_lead_back(&TriangleTopology);
+    }
+}